@@ -0,0 +1,132 @@
+//! Missing-index advisor: FK columns and referenced columns without an index make the check JOIN scan whole tables
+
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::fk::{FkIndex, FkInfo};
+use crate::table::TableInfo;
+
+/// Maximum length of an index name
+const MAX_NAME_LEN: usize = 64;
+
+/// A constraint lacking an index on one side
+#[derive(Debug)]
+pub struct IndexAdvice {
+    pub fk: Rc<FkInfo>,
+    /// The FK columns are the leftmost columns of an index
+    pub child_indexed: bool,
+    /// The referenced columns are the leftmost columns of an index
+    pub parent_indexed: bool,
+    /// Estimated number of rows read by the check, None if the table sizes are unknown
+    pub cost: Option<u64>,
+}
+
+impl IndexAdvice {
+    /// CREATE INDEX statements for the sides without an index
+    pub fn create_statements(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if !self.child_indexed {
+            res.push(create_index(&self.fk.schema, &self.fk.table, &self.fk.columns));
+        }
+        if !self.parent_indexed {
+            res.push(create_index(&self.fk.ref_schema, &self.fk.ref_table, &self.fk.ref_columns));
+        }
+        res
+    }
+}
+
+impl Display for IndexAdvice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.fk.name)?;
+        if !self.child_indexed {
+            write!(f, " no index on table {} {},", self.fk.table, FkInfo::column_desc(&self.fk.columns))?;
+        }
+        if !self.parent_indexed {
+            write!(f, " no index on referenced table {} {},", self.fk.ref_table, FkInfo::column_desc(&self.fk.ref_columns))?;
+        }
+        match self.cost {
+            Some(cost) => write!(f, " estimated check cost {cost} row reads"),
+            None => write!(f, " check cost unknown"),
+        }
+    }
+}
+
+/// eg: CREATE INDEX `idx_line_order_id` ON `shop`.`line` (`order_id`);
+fn create_index(schema: &str, table: &str, columns: &[String]) -> String {
+    let mut name = format!("idx_{table}_{}", columns.join("_"));
+    while name.len() > MAX_NAME_LEN {
+        name.pop();
+    }
+    let columns = columns.iter().map(|c| format!("`{c}`")).collect::<Vec<String>>().join(", ");
+    format!("CREATE INDEX `{name}` ON `{schema}`.`{table}` ({columns});")
+}
+
+/// Rows read by the check: each row of the child table, plus a look up in the parent table which is a scan without index
+fn check_cost(child: &TableInfo, parent: &TableInfo, parent_indexed: bool) -> Option<u64> {
+    let child_rows = child.rows?;
+    if parent_indexed {
+        Some(child_rows)
+    } else {
+        Some(child_rows.saturating_mul(parent.rows?.max(1)))
+    }
+}
+
+/// None if both sides of the constraint have an index or if its tables are unknown.
+/// `tables` must contain the tables of both sides.
+pub fn advise_fk(fk: &Rc<FkInfo>, tables: &[TableInfo]) -> Option<IndexAdvice> {
+    let find_table = |schema: &str, name: &str| tables.iter().find(|t| t.schema == schema && t.name == name);
+    let child = find_table(&fk.schema, &fk.table)?;
+    let parent = find_table(&fk.ref_schema, &fk.ref_table)?;
+    let child_indexed = child.has_leftmost_index(&fk.columns);
+    let parent_indexed = parent.has_leftmost_index(&fk.ref_columns);
+    if child_indexed && parent_indexed {
+        return None;
+    }
+    Some(IndexAdvice { fk: fk.clone(), child_indexed, parent_indexed, cost: check_cost(child, parent, parent_indexed) })
+}
+
+/// The constraints lacking an index on one side. The references embedded in column values can not be indexed.
+pub fn advise(fk_idx: &FkIndex, tables: &[TableInfo]) -> Vec<IndexAdvice> {
+    fk_idx.fks.iter().filter(|fk| !fk.is_embedded()).filter_map(|fk| advise_fk(fk, tables)).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::advise;
+    use crate::ddl;
+    use crate::fk::FkIndex;
+
+    #[test]
+    fn test_advise() {
+        let mut ddl = ddl::parse(r"
+            CREATE TABLE parent (id int PRIMARY KEY, code varchar(10), KEY idx_pair (code, id));
+            CREATE TABLE child (
+                id int PRIMARY KEY, parent_id int, parent_code varchar(10), other_id int,
+                KEY idx_parent (parent_id),
+                CONSTRAINT fk_parent FOREIGN KEY (parent_id) REFERENCES parent (id),
+                CONSTRAINT fk_code FOREIGN KEY (parent_code) REFERENCES parent (code),
+                CONSTRAINT fk_pair FOREIGN KEY (parent_id, parent_code) REFERENCES parent (id, code),
+                CONSTRAINT fk_other FOREIGN KEY (other_id) REFERENCES other (id)
+            );", "sch");
+        ddl.tables[0].rows = Some(100);
+        ddl.tables[1].rows = Some(1000);
+        let fk_idx = FkIndex::from(ddl.fks);
+        let res = advise(&fk_idx, &ddl.tables);
+
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].fk.name, "fk_code");
+        assert_eq!(res[0].to_string(), "fk_code: no index on table child column parent_code, estimated check cost 1000 row reads");
+        assert_eq!(res[0].create_statements(), vec!["CREATE INDEX `idx_child_parent_code` ON `sch`.`child` (`parent_code`);"]);
+
+        assert_eq!(res[1].fk.name, "fk_pair");
+        assert!(!res[1].child_indexed && res[1].parent_indexed);
+
+        // Without index on the parent, each child row scans it
+        ddl.tables[0].indexes.clear();
+        let res = advise(&fk_idx, &ddl.tables);
+        assert_eq!(res[0].fk.name, "fk_parent");
+        assert_eq!(res[0].cost, Some(100_000));
+        assert_eq!(res[0].create_statements(), vec!["CREATE INDEX `idx_parent_id` ON `sch`.`parent` (`id`);"]);
+    }
+}
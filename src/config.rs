@@ -0,0 +1,676 @@
+//! Configuration file (TOML format), declares relationships that MySQL doesn't know about
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::embedded::JsonPath;
+use crate::fk::{Cardinality, Discriminator, FkAction, FkIndex, FkInfo, Validity};
+use crate::report::Severity;
+
+/// Content of the configuration file given with option --config
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Relationships without FK constraint, declared as `[[virtual_fk]]` tables
+    #[serde(default, rename = "virtual_fk")]
+    pub virtual_fks: Vec<VirtualFk>,
+    /// Scope columns of constraints, declared or virtual, as `[[scope]]` tables
+    #[serde(default, rename = "scope")]
+    pub scopes: Vec<Scope>,
+    /// Soft-delete predicates of tables, as `[[alive]]` tables
+    #[serde(default, rename = "alive")]
+    pub alive: Vec<Alive>,
+    /// Validity periods of the referenced rows of constraints, as `[[temporal]]` tables
+    #[serde(default)]
+    pub temporal: Vec<Temporal>,
+    /// Number of children required for the rows of parent tables, as `[[cardinality]]` tables
+    #[serde(default)]
+    pub cardinality: Vec<CardinalityRule>,
+    /// Polymorphic relationships, as `[[polymorphic]]` tables
+    #[serde(default)]
+    pub polymorphic: Vec<PolymorphicFk>,
+    /// References stored in JSON columns, as `[[json_ref]]` tables
+    #[serde(default, rename = "json_ref")]
+    pub json_refs: Vec<JsonRef>,
+    /// References stored as delimited lists in string columns, as `[[id_list]]` tables
+    #[serde(default, rename = "id_list")]
+    pub id_lists: Vec<IdList>,
+    /// Integrity rules which are not Foreign Keys, as `[[assertion]]` tables
+    #[serde(default, rename = "assertion")]
+    pub assertions: Vec<Assertion>,
+}
+
+/// A relationship checked like a Foreign Key, but not declared in the database
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualFk {
+    /// Defaults to vfk_<table>_<columns>
+    pub name: Option<String>,
+    /// Defaults to the schema given with option --schema
+    pub schema: Option<String>,
+    pub table: String,
+    pub columns: Vec<String>,
+    /// Defaults to the schema of the table
+    pub ref_schema: Option<String>,
+    pub ref_table: String,
+    pub ref_columns: Vec<String>,
+    /// Rule of the constraint created when promoting the virtual FK, eg: "SET NULL". Defaults to RESTRICT
+    pub on_delete: Option<FkAction>,
+    /// Columns which must also match between the row and the referenced row, eg: ["tenant_id"]
+    #[serde(default)]
+    pub scope_columns: Vec<String>,
+    /// Defaults to the scope columns
+    pub ref_scope_columns: Option<Vec<String>>,
+}
+
+/// Scope columns of a constraint: a reference to a row of another scope (eg: tenant) is a violation
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scope {
+    pub constraint: String,
+    pub columns: Vec<String>,
+    /// Defaults to the columns
+    pub ref_columns: Option<Vec<String>>,
+}
+
+/// Which rows of a table are alive, the others are soft-deleted: referencing them is a violation
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Alive {
+    /// Defaults to any schema
+    pub schema: Option<String>,
+    pub table: String,
+    /// SQL condition on the columns of the table, eg: "deleted_at IS NULL" or "status <> 'archived'"
+    pub predicate: String,
+}
+
+/// Validity period of the referenced rows of a constraint: the referenced row must be valid at the date of the row
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Temporal {
+    pub constraint: String,
+    /// Column of the table, eg: effective_date
+    pub date_column: String,
+    /// Columns of the referenced table giving the period [valid_from, valid_to), a NULL bound is open
+    pub valid_from: String,
+    pub valid_to: String,
+}
+
+/// Number of rows of a child table required for each row of its parent table, eg: every order has at least one line
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CardinalityRule {
+    /// Defaults to any schema
+    pub schema: Option<String>,
+    /// The parent table
+    pub table: String,
+    pub child_table: String,
+    /// Needed if the child table has several constraints referencing the parent table
+    pub constraint: Option<String>,
+    /// Defaults to 1
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+/// A polymorphic relationship (Rails, Laravel): the type column tells which table the columns reference
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolymorphicFk {
+    /// Defaults to poly_<table>_<columns>
+    pub name: Option<String>,
+    /// Defaults to the schema given with option --schema
+    pub schema: Option<String>,
+    pub table: String,
+    /// eg: commentable_type
+    pub type_column: String,
+    /// eg: ["commentable_id"]
+    pub columns: Vec<String>,
+    /// Referenced table of each value of the type column, other non NULL values are violations
+    pub types: BTreeMap<String, PolymorphicType>,
+}
+
+/// The table referenced by the rows of a polymorphic relationship having a type
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolymorphicType {
+    /// Defaults to the schema of the table
+    pub ref_schema: Option<String>,
+    pub ref_table: String,
+    /// Defaults to ["id"]
+    pub ref_columns: Option<Vec<String>>,
+}
+
+/// References whose child side is a path in the JSON document of a column, eg: the ids of an array
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonRef {
+    /// Defaults to jref_<table>_<column>
+    pub name: Option<String>,
+    /// Defaults to the schema given with option --schema
+    pub schema: Option<String>,
+    pub table: String,
+    pub column: String,
+    /// eg: "$[*]" for an array of ids, "$.owner.id", "$.items[*].product_id". Only one [*] wildcard is allowed.
+    pub path: String,
+    /// Defaults to the schema of the table
+    pub ref_schema: Option<String>,
+    pub ref_table: String,
+    /// Defaults to "id"
+    pub ref_column: Option<String>,
+}
+
+/// References stored as a delimited list in a string column, eg: role_ids = '3,7,12'
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdList {
+    /// Defaults to list_<table>_<column>
+    pub name: Option<String>,
+    /// Defaults to the schema given with option --schema
+    pub schema: Option<String>,
+    pub table: String,
+    pub column: String,
+    /// Defaults to ","
+    pub delimiter: Option<String>,
+    /// Defaults to the schema of the table
+    pub ref_schema: Option<String>,
+    pub ref_table: String,
+    /// Defaults to "id"
+    pub ref_column: Option<String>,
+}
+
+/// A named SQL query returning the rows breaking an integrity rule, eg: orders with a negative total
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Assertion {
+    pub name: String,
+    /// Must return zero rows, eg: "SELECT id FROM shop.order WHERE total < 0"
+    pub query: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Statement run to fix the rows with option --auto-delete, eg: "UPDATE shop.order SET total = 0 WHERE total < 0"
+    pub fix: Option<String>,
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.name, self.severity, self.query)
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl Config {
+    /// Read and parse the config file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> io::Result<Self> {
+        toml::from_str(content).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    /// The virtual FKs, the branches of the polymorphic relationships, the JSON references and the id lists,
+    /// those in another schema than `schema` (if set) are ignored
+    pub fn virtual_fk_infos(&self, schema: Option<&String>) -> io::Result<Vec<FkInfo>> {
+        let mut res = Vec::with_capacity(self.virtual_fks.len());
+        for vfk in self.virtual_fks.iter() {
+            res.push(vfk.to_fk_info(schema)?);
+        }
+        for poly in self.polymorphic.iter() {
+            res.extend(poly.to_fk_infos(schema)?);
+        }
+        for json_ref in self.json_refs.iter() {
+            res.push(json_ref.to_fk_info(schema)?);
+        }
+        for id_list in self.id_lists.iter() {
+            res.push(id_list.to_fk_info(schema)?);
+        }
+        res.retain(|fk| schema.is_none_or(|s| *s == fk.schema));
+        Ok(res)
+    }
+
+    /// Set the scope columns of the `[[scope]]` tables, the predicates of the `[[alive]]` tables,
+    /// the validity periods of the `[[temporal]]` tables and the `[[cardinality]]` rules on the constraints of the index
+    pub fn apply_to(&self, fk_idx: FkIndex) -> io::Result<FkIndex> {
+        if self.scopes.is_empty() && self.alive.is_empty() && self.temporal.is_empty() && self.cardinality.is_empty() {
+            return Ok(fk_idx);
+        }
+        let mut cardinalities: Vec<(String, Cardinality)> = Vec::new();
+        for rule in self.cardinality.iter() {
+            cardinalities.push((rule.constraint_name(&fk_idx)?, rule.cardinality()?));
+        }
+        let mut fks: Vec<FkInfo> = fk_idx.fks.iter().map(|fk| (**fk).clone()).collect();
+        for scope in self.scopes.iter() {
+            let fk = fks.iter_mut().find(|fk| fk.name == scope.constraint)
+                .ok_or_else(|| invalid_data(format!("Scope of unknown constraint {}", scope.constraint)))?;
+            let ref_columns = scope.ref_columns.as_ref().unwrap_or(&scope.columns);
+            check_scope(&fk.name, &scope.columns, ref_columns)?;
+            fk.scope_columns = scope.columns.clone();
+            fk.ref_scope_columns = ref_columns.clone();
+        }
+        for alive in self.alive.iter() {
+            for fk in fks.iter_mut().filter(|fk| fk.ref_table == alive.table && alive.schema.as_ref().is_none_or(|s| *s == fk.ref_schema)) {
+                fk.ref_alive = Some(alive.predicate.clone());
+            }
+        }
+        for temporal in self.temporal.iter() {
+            let fk = fks.iter_mut().find(|fk| fk.name == temporal.constraint)
+                .ok_or_else(|| invalid_data(format!("Temporal rule of unknown constraint {}", temporal.constraint)))?;
+            fk.validity = Some(Validity {
+                date_column: temporal.date_column.clone(),
+                valid_from: temporal.valid_from.clone(),
+                valid_to: temporal.valid_to.clone(),
+            });
+        }
+        for (name, cardinality) in cardinalities {
+            if let Some(fk) = fks.iter_mut().find(|fk| fk.name == name) {
+                fk.cardinality = Some(cardinality);
+            }
+        }
+        Ok(FkIndex::from(fks))
+    }
+}
+
+impl CardinalityRule {
+    /// The constraint of the child table referencing the parent table
+    fn constraint_name(&self, fk_idx: &FkIndex) -> io::Result<String> {
+        let desc = format!("Cardinality of table {} children {}", self.table, self.child_table);
+        let candidates: Vec<_> = fk_idx.fks_by_ref_table.get(&self.table).into_iter().flatten()
+            .filter(|fk| fk.table == self.child_table && self.schema.as_ref().is_none_or(|s| *s == fk.ref_schema))
+            .filter(|fk| self.constraint.as_ref().is_none_or(|c| *c == fk.name))
+            .collect();
+        match candidates[..] {
+            [fk] if fk.is_embedded() => Err(invalid_data(format!("{desc}: the references of {} are embedded in a column value", fk.name))),
+            [fk] => Ok(fk.name.clone()),
+            [] => Err(invalid_data(format!("{desc}: no constraint of the child table references the parent table"))),
+            _ => Err(invalid_data(format!("{desc}: several constraints reference the parent table, set the constraint"))),
+        }
+    }
+
+    fn cardinality(&self) -> io::Result<Cardinality> {
+        let min = self.min.unwrap_or(1);
+        if self.max.is_some_and(|max| max < min) {
+            return Err(invalid_data(format!("Cardinality of table {} children {}: max is lower than min", self.table, self.child_table)));
+        }
+        Ok(Cardinality { min, max: self.max })
+    }
+}
+
+fn check_scope(name: &str, columns: &[String], ref_columns: &[String]) -> io::Result<()> {
+    if columns.len() != ref_columns.len() {
+        return Err(invalid_data(format!("Scope of {name} must have as many columns as referenced columns")));
+    }
+    Ok(())
+}
+
+impl VirtualFk {
+    pub fn to_fk_info(&self, default_schema: Option<&String>) -> io::Result<FkInfo> {
+        let name = self.name.clone()
+            .unwrap_or_else(|| format!("vfk_{}_{}", self.table, self.columns.join("_")));
+        if self.columns.is_empty() || self.columns.len() != self.ref_columns.len() {
+            return Err(invalid_data(format!("Virtual foreign key {name} must have as many columns as referenced columns")));
+        }
+        let ref_scope_columns = self.ref_scope_columns.as_ref().unwrap_or(&self.scope_columns);
+        check_scope(&name, &self.scope_columns, ref_scope_columns)?;
+        let schema = self.schema.as_ref().or(default_schema)
+            .ok_or_else(|| invalid_data(format!("Virtual foreign key {name} has no schema, set it in the config or use --schema")))?;
+        Ok(FkInfo {
+            name,
+            schema: schema.clone(),
+            table: self.table.clone(),
+            columns: self.columns.clone(),
+            ref_schema: self.ref_schema.as_ref().unwrap_or(schema).clone(),
+            ref_table: self.ref_table.clone(),
+            ref_columns: self.ref_columns.clone(),
+            on_delete: self.on_delete.unwrap_or_default(),
+            is_virtual: true,
+            scope_columns: self.scope_columns.clone(),
+            ref_scope_columns: ref_scope_columns.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+impl PolymorphicFk {
+    /// One virtual FK per type, named <name>_<type>
+    pub fn to_fk_infos(&self, default_schema: Option<&String>) -> io::Result<Vec<FkInfo>> {
+        let name = self.name.clone()
+            .unwrap_or_else(|| format!("poly_{}_{}", self.table, self.columns.join("_")));
+        if self.columns.is_empty() || self.types.is_empty() {
+            return Err(invalid_data(format!("Polymorphic relationship {name} must have columns and types")));
+        }
+        let schema = self.schema.as_ref().or(default_schema)
+            .ok_or_else(|| invalid_data(format!("Polymorphic relationship {name} has no schema, set it in the config or use --schema")))?;
+        let mut res = Vec::with_capacity(self.types.len());
+        for (value, target) in self.types.iter() {
+            let ref_columns = target.ref_columns.clone().unwrap_or_else(|| vec![String::from("id")]);
+            if ref_columns.len() != self.columns.len() {
+                return Err(invalid_data(format!("Type {value} of polymorphic relationship {name} must have as many referenced columns as columns")));
+            }
+            // eg: App\Models\Post gives poly_comment_commentable_id_App_Models_Post
+            let suffix: String = value.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+            res.push(FkInfo {
+                name: format!("{name}_{suffix}"),
+                schema: schema.clone(),
+                table: self.table.clone(),
+                columns: self.columns.clone(),
+                ref_schema: target.ref_schema.as_ref().unwrap_or(schema).clone(),
+                ref_table: target.ref_table.clone(),
+                ref_columns,
+                is_virtual: true,
+                discriminator: Some(Discriminator { relationship: name.clone(), column: self.type_column.clone(), value: value.clone() }),
+                ..Default::default()
+            });
+        }
+        Ok(res)
+    }
+}
+
+impl JsonRef {
+    pub fn to_fk_info(&self, default_schema: Option<&String>) -> io::Result<FkInfo> {
+        let name = self.name.clone()
+            .unwrap_or_else(|| format!("jref_{}_{}", self.table, self.column));
+        let path: JsonPath = self.path.parse().map_err(|e| invalid_data(format!("JSON reference {name}: {e}")))?;
+        let schema = self.schema.as_ref().or(default_schema)
+            .ok_or_else(|| invalid_data(format!("JSON reference {name} has no schema, set it in the config or use --schema")))?;
+        Ok(FkInfo {
+            name,
+            schema: schema.clone(),
+            table: self.table.clone(),
+            columns: vec![self.column.clone()],
+            ref_schema: self.ref_schema.as_ref().unwrap_or(schema).clone(),
+            ref_table: self.ref_table.clone(),
+            ref_columns: vec![self.ref_column.clone().unwrap_or_else(|| String::from("id"))],
+            is_virtual: true,
+            json_path: Some(path.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+impl IdList {
+    pub fn to_fk_info(&self, default_schema: Option<&String>) -> io::Result<FkInfo> {
+        let name = self.name.clone()
+            .unwrap_or_else(|| format!("list_{}_{}", self.table, self.column));
+        let delimiter = self.delimiter.clone().unwrap_or_else(|| String::from(","));
+        if delimiter.is_empty() {
+            return Err(invalid_data(format!("Id list {name} has an empty delimiter")));
+        }
+        let schema = self.schema.as_ref().or(default_schema)
+            .ok_or_else(|| invalid_data(format!("Id list {name} has no schema, set it in the config or use --schema")))?;
+        Ok(FkInfo {
+            name,
+            schema: schema.clone(),
+            table: self.table.clone(),
+            columns: vec![self.column.clone()],
+            ref_schema: self.ref_schema.as_ref().unwrap_or(schema).clone(),
+            ref_table: self.ref_table.clone(),
+            ref_columns: vec![self.ref_column.clone().unwrap_or_else(|| String::from("id"))],
+            is_virtual: true,
+            delimiter: Some(delimiter),
+            ..Default::default()
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use crate::ddl;
+    use crate::fk::{Cardinality, FkAction, FkIndex};
+    use crate::report::Severity;
+
+    const CONFIG: &str = r#"
+        [[virtual_fk]]
+        table = "order"
+        columns = ["customer_id"]
+        ref_table = "customer"
+        ref_columns = ["id"]
+        scope_columns = ["tenant_id"]
+
+        [[virtual_fk]]
+        name = "line_product"
+        schema = "other"
+        table = "line"
+        columns = ["product_id", "variant"]
+        ref_table = "product"
+        ref_columns = ["id", "variant"]
+        on_delete = "cascade"
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(CONFIG).expect("config should parse");
+        assert_eq!(config.virtual_fks.len(), 2);
+        assert_eq!(config.virtual_fks[1].name.as_deref(), Some("line_product"));
+        assert_eq!(config.virtual_fks[1].columns, vec!["product_id", "variant"]);
+        assert!(config.virtual_fks[0].on_delete.is_none());
+        assert_eq!(config.virtual_fks[1].on_delete, Some(FkAction::Cascade));
+    }
+
+    #[test]
+    fn test_parse_unknown_field() {
+        assert!(Config::parse("[[virtual_fk]]\nfoo = 1").is_err());
+    }
+
+    #[test]
+    fn test_virtual_fk_infos() {
+        let config = Config::parse(CONFIG).unwrap();
+        config.virtual_fk_infos(None).expect_err("first virtual FK has no schema");
+
+        let fks = config.virtual_fk_infos(Some(&String::from("shop"))).expect("virtual FKs should be valid");
+        assert_eq!(fks.len(), 1); // line_product is in another schema
+        assert_eq!(fks[0].name, "vfk_order_customer_id");
+        assert_eq!(fks[0].schema, "shop");
+        assert_eq!(fks[0].ref_schema, "shop");
+        assert!(fks[0].is_virtual);
+        assert_eq!(fks[0].scope_columns, vec!["tenant_id"]);
+        assert_eq!(fks[0].ref_scope_columns, vec!["tenant_id"]);
+    }
+
+    #[test]
+    fn test_apply_to() {
+        let config = Config::parse(r#"
+            [[scope]]
+            constraint = "a_ibfk_1"
+            columns = ["tenant_id"]
+            ref_columns = ["tenant"]
+        "#).unwrap();
+        let ddl = ddl::parse("CREATE TABLE a (b_id int REFERENCES b (id), c_id int REFERENCES c (id));", "sch");
+        let fk_idx = config.apply_to(FkIndex::from(ddl.fks.clone())).expect("scope should apply");
+        assert_eq!(fk_idx.fks_by_name["a_ibfk_1"].scope_columns, vec!["tenant_id"]);
+        assert_eq!(fk_idx.fks_by_name["a_ibfk_1"].ref_scope_columns, vec!["tenant"]);
+        assert!(fk_idx.fks_by_name["a_ibfk_2"].scope_columns.is_empty());
+
+        let config = Config::parse("[[scope]]\nconstraint = \"other\"\ncolumns = [\"tenant_id\"]").unwrap();
+        let res = config.apply_to(FkIndex::from(Vec::new()));
+        assert!(res.err().expect("unknown constraint").to_string().contains("unknown constraint other"));
+
+        let config = Config::parse(r#"
+            [[alive]]
+            table = "c"
+            predicate = "deleted_at IS NULL"
+
+            [[alive]]
+            schema = "other"
+            table = "b"
+            predicate = "status <> 'archived'"
+        "#).unwrap();
+        let fk_idx = config.apply_to(FkIndex::from(ddl.fks.clone())).expect("alive should apply");
+        assert!(fk_idx.fks_by_name["a_ibfk_1"].ref_alive.is_none());
+        assert_eq!(fk_idx.fks_by_name["a_ibfk_2"].ref_alive.as_deref(), Some("deleted_at IS NULL"));
+
+        let config = Config::parse(r#"
+            [[temporal]]
+            constraint = "a_ibfk_1"
+            date_column = "effective_date"
+            valid_from = "valid_from"
+            valid_to = "valid_to"
+        "#).unwrap();
+        let fk_idx = config.apply_to(FkIndex::from(ddl.fks.clone())).expect("temporal rule should apply");
+        assert!(fk_idx.fks_by_name["a_ibfk_1"].to_string().ends_with("referencing table b column id valid at effective_date within [valid_from, valid_to)"));
+        assert!(fk_idx.fks_by_name["a_ibfk_2"].validity.is_none());
+        let config = Config::parse("[[temporal]]\nconstraint = \"other\"\ndate_column = \"d\"\nvalid_from = \"f\"\nvalid_to = \"t\"").unwrap();
+        assert!(config.apply_to(FkIndex::from(ddl.fks)).is_err());
+    }
+
+    #[test]
+    fn test_polymorphic() {
+        let config = Config::parse(r#"
+            [[polymorphic]]
+            table = "comment"
+            type_column = "commentable_type"
+            columns = ["commentable_id"]
+
+            [polymorphic.types.Post]
+            ref_table = "post"
+
+            [polymorphic.types.'App\Models\Video']
+            ref_table = "video"
+            ref_columns = ["uuid"]
+        "#).unwrap();
+        let fks = config.virtual_fk_infos(Some(&String::from("shop"))).expect("polymorphic relationship should be valid");
+        assert_eq!(fks.len(), 2);
+        assert_eq!(fks[0].name, "poly_comment_commentable_id_App_Models_Video");
+        assert_eq!(fks[0].ref_columns, vec!["uuid"]);
+        assert_eq!(fks[1].name, "poly_comment_commentable_id_Post");
+        assert_eq!(fks[1].ref_columns, vec!["id"]);
+        let discriminator = fks[0].discriminator.as_ref().expect("branch of a polymorphic relationship");
+        assert_eq!(discriminator.relationship, "poly_comment_commentable_id");
+        assert_eq!(discriminator.value, "App\\Models\\Video");
+        assert_eq!(discriminator.literal(), "'App\\\\Models\\\\Video'");
+
+        let fk_idx = FkIndex::from(fks);
+        let poly = fk_idx.polymorphic();
+        assert_eq!(poly.len(), 1);
+        assert_eq!(poly[0].to_string(), "poly_comment_commentable_id in schema shop on table comment column commentable_id typed by column commentable_type, types App\\Models\\Video, Post");
+
+        let config = Config::parse("[[polymorphic]]\nschema = \"s\"\ntable = \"a\"\ntype_column = \"t\"\ncolumns = [\"x\"]\ntypes = {}").unwrap();
+        assert!(config.virtual_fk_infos(None).is_err());
+    }
+
+    #[test]
+    fn test_json_refs() {
+        let config = Config::parse(r#"
+            [[json_ref]]
+            table = "post"
+            column = "tags"
+            path = "$[*]"
+            ref_table = "tag"
+
+            [[json_ref]]
+            name = "doc_owner"
+            table = "doc"
+            column = "meta"
+            path = "$.owner.id"
+            ref_table = "user"
+            ref_column = "uid"
+        "#).unwrap();
+        let fks = config.virtual_fk_infos(Some(&String::from("sch"))).expect("JSON references should be valid");
+        assert_eq!(fks[0].to_string(), "jref_post_tags in schema sch on table post column tags referencing table tag column id at JSON path $[*] (virtual)");
+        assert!(fks[0].is_embedded() && !fks[0].can_be_constraint());
+        assert_eq!(fks[1].name, "doc_owner");
+        assert_eq!(fks[1].ref_columns, vec!["uid"]);
+
+        let config = Config::parse("[[json_ref]]\nschema = \"s\"\ntable = \"a\"\ncolumn = \"c\"\npath = \"$[*].ids[*]\"\nref_table = \"b\"").unwrap();
+        assert!(config.virtual_fk_infos(None).err().expect("two wildcards").to_string().contains("only one [*] wildcard"));
+    }
+
+    #[test]
+    fn test_id_lists() {
+        let config = Config::parse(r#"
+            [[id_list]]
+            table = "user"
+            column = "role_ids"
+            ref_table = "role"
+
+            [[id_list]]
+            schema = "other"
+            table = "user"
+            column = "group_ids"
+            delimiter = "|"
+            ref_table = "group"
+        "#).unwrap();
+        let fks = config.virtual_fk_infos(Some(&String::from("sch"))).expect("id lists should be valid");
+        assert_eq!(fks.len(), 1);
+        assert_eq!(fks[0].to_string(), "list_user_role_ids in schema sch on table user column role_ids referencing table role column id as a list delimited by ',' (virtual)");
+        assert!(fks[0].is_embedded());
+        assert_eq!(config.id_lists[1].to_fk_info(None).unwrap().delimiter.as_deref(), Some("|"));
+
+        let config = Config::parse("[[id_list]]\nschema = \"s\"\ntable = \"a\"\ncolumn = \"c\"\ndelimiter = \"\"\nref_table = \"b\"").unwrap();
+        assert!(config.virtual_fk_infos(None).is_err());
+    }
+
+    #[test]
+    fn test_cardinality() {
+        let ddl = ddl::parse(r"
+            CREATE TABLE line (order_id int REFERENCES `order` (id));
+            CREATE TABLE invoice (order_id int REFERENCES `order` (id), refund_id int REFERENCES `order` (id));", "sch");
+        let config = Config::parse(r#"
+            [[cardinality]]
+            table = "order"
+            child_table = "line"
+            max = 50
+
+            [[cardinality]]
+            table = "order"
+            child_table = "invoice"
+            constraint = "invoice_ibfk_1"
+            min = 0
+            max = 1
+        "#).unwrap();
+        let fk_idx = config.apply_to(FkIndex::from(ddl.fks.clone())).expect("cardinality should apply");
+        assert_eq!(fk_idx.fks_by_name["line_ibfk_1"].cardinality, Some(Cardinality { min: 1, max: Some(50) }));
+        assert_eq!(fk_idx.fks_by_name["invoice_ibfk_1"].cardinality.expect("cardinality").to_string(), "0 to 1");
+        assert!(fk_idx.fks_by_name["invoice_ibfk_2"].cardinality.is_none());
+
+        let config = Config::parse("[[cardinality]]\ntable = \"order\"\nchild_table = \"invoice\"").unwrap();
+        let res = config.apply_to(FkIndex::from(ddl.fks.clone()));
+        assert!(res.err().expect("ambiguous").to_string().contains("several constraints"));
+        let config = Config::parse("[[cardinality]]\ntable = \"order\"\nchild_table = \"line\"\nmin = 2\nmax = 1").unwrap();
+        assert!(config.apply_to(FkIndex::from(ddl.fks)).is_err());
+    }
+
+    #[test]
+    fn test_assertions() {
+        let config = Config::parse(r#"
+            [[assertion]]
+            name = "negative_total"
+            query = "SELECT id FROM shop.order WHERE total < 0"
+            fix = "UPDATE shop.order SET total = 0 WHERE total < 0"
+
+            [[assertion]]
+            name = "active_subscriptions"
+            query = "SELECT user_id FROM shop.subscription WHERE active GROUP BY user_id HAVING COUNT(*) > 1"
+            severity = "warning"
+        "#).unwrap();
+        assert_eq!(config.assertions.len(), 2);
+        assert_eq!(config.assertions[0].severity, Severity::Error);
+        assert!(config.assertions[0].fix.is_some());
+        assert_eq!(config.assertions[1].to_string(), "active_subscriptions (warning): SELECT user_id FROM shop.subscription WHERE active GROUP BY user_id HAVING COUNT(*) > 1");
+
+        assert!(Config::parse("[[assertion]]\nname = \"a\"\nquery = \"SELECT 1\"\nseverity = \"fatal\"").is_err());
+    }
+
+    #[test]
+    fn test_columns_mismatch() {
+        let config = Config::parse(r#"
+            [[virtual_fk]]
+            schema = "s"
+            table = "a"
+            columns = ["x", "y"]
+            ref_table = "b"
+            ref_columns = ["id"]
+        "#).unwrap();
+        assert!(config.virtual_fks[0].to_fk_info(None).is_err());
+    }
+}
@@ -0,0 +1,798 @@
+//! Offline schema: parse the CREATE TABLE statements of a .sql file or mysqldump, without a server
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use crate::fk::{FkAction, FkInfo};
+use crate::table::{ColumnInfo, IndexInfo, TableInfo};
+
+/// Tables and FK constraints found in a DDL file
+#[derive(Debug, Default)]
+pub struct DdlSchema {
+    pub tables: Vec<TableInfo>,
+    pub fks: Vec<FkInfo>,
+}
+
+impl DdlSchema {
+    /// Add the tables and FKs of a statement, ignores anything but CREATE TABLE and ALTER TABLE
+    pub fn add_statement(&mut self, stmt: &str, schema: &mut String) {
+        match parse_statement_after(stmt, schema, &self.fks) {
+            Statement::Use(name) => *schema = name,
+            Statement::CreateTable(table, fks) => {
+                self.tables.push(table);
+                self.fks.extend(fks);
+            }
+            Statement::AlterTable(fks) => self.fks.extend(fks),
+            Statement::Other => {}
+        }
+    }
+
+    pub fn table(&self, schema: &str, name: &str) -> Option<&TableInfo> {
+        self.tables.iter().find(|t| t.schema == schema && t.name == name)
+    }
+}
+
+/// Parse a DDL file. `schema` is used for the tables until a USE statement.
+pub fn parse_file(path: &Path, schema: &str) -> io::Result<DdlSchema> {
+    let mut res = DdlSchema::default();
+    let mut schema = schema.to_string();
+    for stmt in Statements::new(BufReader::new(File::open(path)?)) {
+        res.add_statement(&stmt?, &mut schema);
+    }
+    Ok(res)
+}
+
+/// Parse DDL statements from a string
+pub fn parse(sql: &str, schema: &str) -> DdlSchema {
+    let mut res = DdlSchema::default();
+    let mut schema = schema.to_string();
+    for stmt in Statements::new(sql.as_bytes()).map_while(|s| s.ok()) {
+        res.add_statement(&stmt, &mut schema);
+    }
+    res
+}
+
+/// Splits SQL into statements, line by line, so that a big dump is never loaded in memory.
+/// Comments are removed, except versioned comments (/*!40101 ... */) whose content is kept as SQL.
+/// Handles the DELIMITER command of the mysql client.
+pub struct Statements<R> {
+    reader: R,
+    delimiter: String,
+    current: String,
+    pending: VecDeque<String>,
+    /// Quote character of the string being read
+    quote: Option<char>,
+    in_comment: bool,
+    in_versioned_comment: bool,
+}
+
+impl<R: BufRead> Statements<R> {
+    pub fn new(reader: R) -> Self {
+        Statements { reader, delimiter: String::from(";"), current: String::new(), pending: VecDeque::new(), quote: None, in_comment: false, in_versioned_comment: false }
+    }
+
+    fn push_statement(&mut self) {
+        let stmt = self.current.trim();
+        if !stmt.is_empty() {
+            self.pending.push_back(stmt.to_string());
+        }
+        self.current.clear();
+    }
+
+    fn scan_line(&mut self, line: &str) {
+        if self.quote.is_none() && !self.in_comment && self.current.trim().is_empty() {
+            let trimmed = line.trim();
+            // get() rather than slicing: byte 10 may be inside a multi-byte char, eg: "-- Résumé"
+            let is_delimiter = trimmed.get(..10).is_some_and(|p| p.eq_ignore_ascii_case("DELIMITER "));
+            if let Some(delimiter) = trimmed.get(10..).filter(|_| is_delimiter) {
+                self.delimiter = delimiter.trim().to_string();
+                return;
+            }
+        }
+
+        let mut idx = 0;
+        while idx < line.len() {
+            let rest = &line[idx..];
+            let c = rest.chars().next().unwrap();
+            if self.in_comment {
+                if rest.starts_with("*/") {
+                    self.in_comment = false;
+                    idx += 2;
+                } else {
+                    idx += c.len_utf8();
+                }
+                continue;
+            }
+            if let Some(q) = self.quote {
+                if c == '\\' && q != '`' {
+                    let escaped = rest.chars().nth(1).map(|e| e.len_utf8()).unwrap_or(0);
+                    self.current.push_str(&rest[..1 + escaped]);
+                    idx += 1 + escaped;
+                    continue;
+                }
+                self.current.push(c);
+                idx += c.len_utf8();
+                if c == q {
+                    if rest[1..].starts_with(q) {
+                        // Doubled quote
+                        self.current.push(q);
+                        idx += 1;
+                    } else {
+                        self.quote = None;
+                    }
+                }
+                continue;
+            }
+            if rest.starts_with("-- ") || rest.starts_with("--\n") || rest.starts_with("--\r") || rest == "--" || c == '#' {
+                self.current.push('\n');
+                break;
+            }
+            if rest.starts_with("/*!") {
+                // Versioned comment: keep the content, skip the version number
+                self.in_versioned_comment = true;
+                idx += 3;
+                idx += line[idx..].chars().take_while(|d| d.is_ascii_digit()).count();
+                self.current.push(' ');
+                continue;
+            }
+            if rest.starts_with("/*") {
+                self.in_comment = true;
+                idx += 2;
+                continue;
+            }
+            if self.in_versioned_comment && rest.starts_with("*/") {
+                self.in_versioned_comment = false;
+                self.current.push(' ');
+                idx += 2;
+                continue;
+            }
+            if rest.starts_with(self.delimiter.as_str()) {
+                self.push_statement();
+                idx += self.delimiter.len();
+                continue;
+            }
+            if c == '\'' || c == '"' || c == '`' {
+                self.quote = Some(c);
+            }
+            self.current.push(c);
+            idx += c.len_utf8();
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Statements<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        while self.pending.is_empty() {
+            line.clear();
+            match self.reader.read_until(b'\n', &mut line) {
+                Err(e) => return Some(Err(e)),
+                Ok(0) => {
+                    // Last statement without delimiter
+                    self.push_statement();
+                    break;
+                }
+                // Binary data of a dump made without --hex-blob is not valid UTF-8
+                Ok(_) => self.scan_line(&String::from_utf8_lossy(&line)),
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// A token of a SQL statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Keyword or unquoted identifier or number
+    Word(String),
+    /// `identifier`
+    Quoted(String),
+    /// 'string' or "string", unescaped
+    Str(String),
+    Sym(char),
+}
+
+impl Token {
+    fn is_kw(&self, kw: &str) -> bool {
+        matches!(self, Token::Word(w) if w.eq_ignore_ascii_case(kw))
+    }
+
+    pub fn ident(&self) -> Option<&str> {
+        match self {
+            Token::Word(w) | Token::Quoted(w) => Some(w),
+            _ => None,
+        }
+    }
+}
+
+/// Split a statement into tokens
+pub fn tokenize(stmt: &str) -> Vec<Token> {
+    let mut res = Vec::new();
+    let mut chars = stmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' || c == '$' {
+            let mut word = String::from(c);
+            while let Some(&n) = chars.peek() {
+                if !(n.is_alphanumeric() || n == '_' || n == '$') {
+                    break;
+                }
+                word.push(n);
+                chars.next();
+            }
+            res.push(Token::Word(word));
+        } else if c == '`' || c == '\'' || c == '"' {
+            let value = read_quoted(&mut chars, c);
+            res.push(if c == '`' { Token::Quoted(value) } else { Token::Str(value) });
+        } else {
+            res.push(Token::Sym(c));
+        }
+    }
+    res
+}
+
+/// Read a quoted string or identifier, after its opening `quote`, up to the closing one. Returns it unescaped.
+pub fn read_quoted(chars: &mut Peekable<Chars>, quote: char) -> String {
+    let mut value = String::new();
+    while let Some(n) = chars.next() {
+        if n == '\\' && quote != '`' {
+            match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('0') => value.push('\0'),
+                Some('Z') => value.push('\x1a'),
+                Some(e) => value.push(e),
+                None => {}
+            }
+        } else if n == quote {
+            if chars.peek() == Some(&quote) {
+                value.push(quote);
+                chars.next();
+            } else {
+                break;
+            }
+        } else {
+            value.push(n);
+        }
+    }
+    value
+}
+
+/// Statements understood by the DDL parser
+#[derive(Debug)]
+pub enum Statement {
+    Use(String),
+    CreateTable(TableInfo, Vec<FkInfo>),
+    AlterTable(Vec<FkInfo>),
+    Other,
+}
+
+/// Cursor over the tokens of a statement
+struct Parser<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn new(tokens: &'t [Token]) -> Self {
+        Parser { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'t Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'t Token> {
+        let res = self.tokens.get(self.pos);
+        self.pos += 1;
+        res
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// Consume the keyword if it is the next token
+    fn eat_kw(&mut self, kw: &str) -> bool {
+        if self.peek().is_some_and(|t| t.is_kw(kw)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume the keywords if they are the next tokens
+    fn eat_kws(&mut self, kws: &[&str]) -> bool {
+        let matches = kws.iter().enumerate().all(|(i, kw)| self.tokens.get(self.pos + i).is_some_and(|t| t.is_kw(kw)));
+        if matches {
+            self.pos += kws.len();
+        }
+        matches
+    }
+
+    fn eat_sym(&mut self, sym: char) -> bool {
+        if self.peek() == Some(&Token::Sym(sym)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        let res = self.peek()?.ident()?.to_string();
+        self.pos += 1;
+        Some(res)
+    }
+
+    /// [schema.]table, schema defaults to `schema`
+    fn table_name(&mut self, schema: &str) -> Option<(String, String)> {
+        let first = self.ident()?;
+        if self.eat_sym('.') {
+            Some((first, self.ident()?))
+        } else {
+            Some((schema.to_string(), first))
+        }
+    }
+
+    /// Skip a parenthesized group, the next token must be '('
+    fn skip_group(&mut self) -> Option<&'t [Token]> {
+        if !self.eat_sym('(') {
+            return None;
+        }
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(t) = self.next() {
+            match t {
+                Token::Sym('(') => depth += 1,
+                Token::Sym(')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(&self.tokens[start..self.pos - 1]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Column list of a key, eg: (`a`, `b`(10) DESC). Expressions are skipped.
+    fn column_list(&mut self) -> Option<Vec<String>> {
+        let group = self.skip_group()?;
+        Some(split_top_level(group).iter()
+            .filter_map(|part| part.first().and_then(|t| t.ident()).map(String::from))
+            .collect())
+    }
+
+    /// RESTRICT | CASCADE | SET NULL | NO ACTION | SET DEFAULT
+    fn action(&mut self) -> Option<FkAction> {
+        for kws in [&["RESTRICT"][..], &["CASCADE"], &["SET", "NULL"], &["NO", "ACTION"], &["SET", "DEFAULT"]] {
+            if self.eat_kws(kws) {
+                return kws.join(" ").parse().ok();
+            }
+        }
+        None
+    }
+
+    /// REFERENCES tbl (cols) [MATCH x] [ON DELETE action] [ON UPDATE action], the REFERENCES keyword already consumed
+    fn references(&mut self, fk: &mut FkInfo) -> Option<()> {
+        let (ref_schema, ref_table) = self.table_name(&fk.schema)?;
+        fk.ref_schema = ref_schema;
+        fk.ref_table = ref_table;
+        fk.ref_columns = self.column_list()?;
+        loop {
+            if self.eat_kw("MATCH") {
+                self.next();
+            } else if self.eat_kws(&["ON", "DELETE"]) {
+                fk.on_delete = self.action()?;
+            } else if self.eat_kws(&["ON", "UPDATE"]) {
+                self.action()?;
+            } else {
+                return Some(());
+            }
+        }
+    }
+}
+
+/// Split tokens on the commas which are not in parenthesis
+fn split_top_level(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, t) in tokens.iter().enumerate() {
+        match t {
+            Token::Sym('(') => depth += 1,
+            Token::Sym(')') => depth -= 1,
+            Token::Sym(',') if depth == 0 => {
+                res.push(&tokens[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < tokens.len() {
+        res.push(&tokens[start..]);
+    }
+    res
+}
+
+/// Format a data type like information_schema.COLUMNS.COLUMN_TYPE, eg: bigint(20) unsigned
+fn format_type(tokens: &[Token]) -> String {
+    let mut res = String::new();
+    for t in tokens {
+        match t {
+            Token::Word(w) | Token::Quoted(w) => {
+                if !res.is_empty() && !res.ends_with('(') && !res.ends_with(',') {
+                    res.push(' ');
+                }
+                res.push_str(&w.to_lowercase());
+            }
+            Token::Str(s) => res += &format!("'{}'", s.replace('\'', "''")),
+            Token::Sym(c) => {
+                if *c == '(' {
+                    res = res.trim_end().to_string();
+                }
+                res.push(*c);
+            }
+        }
+    }
+    res
+}
+
+/// Table being parsed, and the number of its unnamed FKs
+struct TableParser {
+    table: TableInfo,
+    fks: Vec<FkInfo>,
+    unnamed_fks: usize,
+}
+
+impl TableParser {
+    /// `fks` are the FKs found before, InnoDB numbers the unnamed FKs of ALTER TABLE after the highest of the table
+    fn new(schema: String, name: String, fks: &[FkInfo]) -> Self {
+        let prefix = format!("{name}_ibfk_");
+        let unnamed_fks = fks.iter()
+            .filter(|fk| fk.schema == schema && fk.table == name)
+            .filter_map(|fk| fk.name.strip_prefix(&prefix)?.parse().ok())
+            .max()
+            .unwrap_or(0);
+        TableParser { table: TableInfo { schema, name, ..Default::default() }, fks: Vec::new(), unnamed_fks }
+    }
+
+    fn new_fk(&mut self, name: Option<String>, columns: Vec<String>) -> FkInfo {
+        let name = name.unwrap_or_else(|| {
+            // InnoDB naming of unnamed constraints
+            self.unnamed_fks += 1;
+            format!("{}_ibfk_{}", self.table.name, self.unnamed_fks)
+        });
+        FkInfo {
+            name,
+            schema: self.table.schema.clone(),
+            table: self.table.name.clone(),
+            columns,
+            ..Default::default()
+        }
+    }
+
+    /// Column, index or constraint definition
+    fn definition(&mut self, tokens: &[Token]) {
+        let mut p = Parser::new(tokens);
+        let constraint = if p.eat_kw("CONSTRAINT") {
+            // The symbol is optional
+            if p.peek().is_some_and(|t| !t.is_kw("PRIMARY") && !t.is_kw("UNIQUE") && !t.is_kw("FOREIGN") && !t.is_kw("CHECK")) {
+                p.ident()
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        if p.eat_kws(&["PRIMARY", "KEY"]) {
+            while p.peek().is_some_and(|t| t != &Token::Sym('(')) {
+                p.next(); // USING BTREE
+            }
+            if let Some(columns) = p.column_list() {
+                self.add_index(String::from("PRIMARY"), true, columns.clone());
+                self.table.primary_key = columns;
+            }
+        } else if p.peek().is_some_and(|t| t.is_kw("UNIQUE") || t.is_kw("KEY") || t.is_kw("INDEX")) {
+            let unique = p.eat_kw("UNIQUE");
+            let _ = p.eat_kw("KEY") || p.eat_kw("INDEX");
+            let name = if p.peek().is_some_and(|t| t.ident().is_some() && !t.is_kw("USING")) { p.ident() } else { None };
+            while p.peek().is_some_and(|t| t != &Token::Sym('(')) {
+                p.next(); // USING BTREE
+            }
+            if let Some(columns) = p.column_list() {
+                // Unnamed indexes are named after their first column
+                if let Some(name) = name.or(constraint).or_else(|| columns.first().cloned()) {
+                    self.add_index(name, unique, columns);
+                }
+            }
+        } else if p.eat_kws(&["FOREIGN", "KEY"]) {
+            if p.peek().is_some_and(|t| t.ident().is_some()) {
+                p.next(); // index name
+            }
+            let Some(columns) = p.column_list() else {
+                return;
+            };
+            let mut fk = self.new_fk(constraint, columns);
+            if p.eat_kw("REFERENCES") && p.references(&mut fk).is_some() {
+                self.fks.push(fk);
+            }
+        } else if constraint.is_some() || ["FULLTEXT", "SPATIAL", "CHECK"].iter().any(|kw| p.peek().is_some_and(|t| t.is_kw(kw))) {
+            // Full text and spatial indexes, checks
+        } else if let Some(name) = p.ident() {
+            self.column(name, &mut p);
+        }
+    }
+
+    /// Functional indexes (no column) are ignored
+    fn add_index(&mut self, name: String, unique: bool, columns: Vec<String>) {
+        if !columns.is_empty() {
+            self.table.indexes.push(IndexInfo { name, unique, columns });
+        }
+    }
+
+    fn column(&mut self, name: String, p: &mut Parser) {
+        let start = p.pos;
+        let Some(data_type) = p.ident() else {
+            return;
+        };
+        if p.peek() == Some(&Token::Sym('(')) {
+            p.skip_group();
+        }
+        while p.eat_kw("UNSIGNED") || p.eat_kw("SIGNED") || p.eat_kw("ZEROFILL") {}
+        let column_type = format_type(&p.tokens[start..p.pos]);
+        let mut column = ColumnInfo { name: name.clone(), data_type: data_type.to_lowercase(), column_type, ..Default::default() };
+
+        // Attributes: only inline PRIMARY KEY, UNIQUE, REFERENCES, CHARACTER SET and COLLATE are used
+        while !p.at_end() {
+            if p.eat_kws(&["PRIMARY", "KEY"]) {
+                self.add_index(String::from("PRIMARY"), true, vec![name.clone()]);
+                self.table.primary_key = vec![name.clone()];
+            } else if p.eat_kw("UNIQUE") {
+                p.eat_kw("KEY");
+                self.add_index(name.clone(), true, vec![name.clone()]);
+            } else if p.eat_kws(&["CHARACTER", "SET"]) || p.eat_kw("CHARSET") {
+                column.charset = p.ident();
+            } else if p.eat_kw("COLLATE") {
+                column.collation = p.ident();
+            } else if p.eat_kw("REFERENCES") {
+                let mut fk = self.new_fk(None, vec![name.clone()]);
+                if p.references(&mut fk).is_some() {
+                    self.fks.push(fk);
+                }
+            } else if p.peek() == Some(&Token::Sym('(')) {
+                p.skip_group();
+            } else {
+                p.next();
+            }
+        }
+        self.table.columns.push(column);
+    }
+
+    /// Table options after the definitions, eg: ENGINE=InnoDB
+    fn options(&mut self, p: &mut Parser) {
+        let mut charset = None;
+        let mut collation = None;
+        while !p.at_end() {
+            if p.eat_kw("ENGINE") {
+                p.eat_sym('=');
+                self.table.engine = p.ident();
+            } else if p.eat_kws(&["CHARACTER", "SET"]) || p.eat_kw("CHARSET") {
+                p.eat_sym('=');
+                charset = p.ident();
+            } else if p.eat_kw("COLLATE") {
+                p.eat_sym('=');
+                collation = p.ident();
+            } else if p.eat_kws(&["PARTITION", "BY"]) {
+                self.table.partitioned = true;
+            } else {
+                p.next();
+            }
+        }
+
+        // The charset of a collation is its prefix, eg: utf8mb4_general_ci
+        let collation_charset = |c: &String| c.split('_').next().unwrap_or_default().to_string();
+        let charset = charset.or_else(|| collation.as_ref().map(collation_charset));
+        for column in self.table.columns.iter_mut().filter(|c| TEXT_TYPES.contains(&c.data_type.as_str())) {
+            if column.charset.is_none() {
+                column.charset = column.collation.as_ref().map(collation_charset).or_else(|| charset.clone());
+            }
+            // The default collation of another charset is unknown without a server
+            if column.collation.is_none() && column.charset == charset {
+                column.collation = collation.clone();
+            }
+        }
+    }
+}
+
+/// Types having a charset and a collation
+const TEXT_TYPES: [&str; 8] = ["char", "varchar", "tinytext", "text", "mediumtext", "longtext", "enum", "set"];
+
+/// Parse a statement, `schema` is the current schema (USE statement)
+pub fn parse_statement(stmt: &str, schema: &str) -> Statement {
+    parse_statement_after(stmt, schema, &[])
+}
+
+/// Parse a statement following the statements which declared `fks`
+fn parse_statement_after(stmt: &str, schema: &str, fks: &[FkInfo]) -> Statement {
+    // Avoid tokenizing the INSERT statements of a full dump
+    let head: String = stmt.chars().take(6).collect::<String>().to_uppercase();
+    if !["CREATE", "ALTER ", "USE "].iter().any(|kw| head.starts_with(kw)) {
+        return Statement::Other;
+    }
+    let tokens = tokenize(stmt);
+    let mut p = Parser::new(&tokens);
+    if p.eat_kw("USE") {
+        return p.ident().map(Statement::Use).unwrap_or(Statement::Other);
+    }
+    if p.eat_kw("CREATE") {
+        p.eat_kw("TEMPORARY");
+        if !p.eat_kw("TABLE") {
+            return Statement::Other;
+        }
+        p.eat_kws(&["IF", "NOT", "EXISTS"]);
+        let Some((schema, name)) = p.table_name(schema) else {
+            return Statement::Other;
+        };
+        let mut tp = TableParser::new(schema, name, fks);
+        // CREATE TABLE ... LIKE has no definitions
+        let Some(definitions) = p.skip_group() else {
+            return Statement::Other;
+        };
+        for definition in split_top_level(definitions) {
+            tp.definition(definition);
+        }
+        tp.options(&mut p);
+        return Statement::CreateTable(tp.table, tp.fks);
+    }
+    if p.eat_kws(&["ALTER", "TABLE"]) {
+        let Some((schema, name)) = p.table_name(schema) else {
+            return Statement::Other;
+        };
+        let mut tp = TableParser::new(schema, name, fks);
+        for spec in split_top_level(&tokens[p.pos..]) {
+            if spec.first().is_some_and(|t| t.is_kw("ADD")) {
+                tp.definition(&spec[1..]);
+            }
+        }
+        return Statement::AlterTable(tp.fks);
+    }
+    Statement::Other
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_statements() {
+        let sql = "-- comment\n-- Résumé des tables\n/*!40101 SET NAMES utf8 */;\nINSERT INTO `a` VALUES (1,'x;y'),(2,'it''s \\';');\n/* block; comment */\nDELIMITER ;;\nCREATE TRIGGER t BEFORE INSERT ON a FOR EACH ROW BEGIN SET @x=1; END ;;\nDELIMITER ;\nSELECT 1 # end\n";
+        let res: Vec<String> = Statements::new(sql.as_bytes()).map(|s| s.unwrap()).collect();
+        assert_eq!(res, vec![
+            "SET NAMES utf8",
+            "INSERT INTO `a` VALUES (1,'x;y'),(2,'it''s \\';')",
+            "CREATE TRIGGER t BEFORE INSERT ON a FOR EACH ROW BEGIN SET @x=1; END",
+            "SELECT 1",
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let res = tokenize("CREATE TABLE `a``b` (x int(11) DEFAULT 'it''s')");
+        assert_eq!(res, vec![
+            Token::Word(String::from("CREATE")),
+            Token::Word(String::from("TABLE")),
+            Token::Quoted(String::from("a`b")),
+            Token::Sym('('),
+            Token::Word(String::from("x")),
+            Token::Word(String::from("int")),
+            Token::Sym('('),
+            Token::Word(String::from("11")),
+            Token::Sym(')'),
+            Token::Word(String::from("DEFAULT")),
+            Token::Str(String::from("it's")),
+            Token::Sym(')'),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_test_dump() {
+        let sql = std::fs::read_to_string("tests/invalid_foreign_ref.sql").unwrap();
+        let res = parse(&sql, "my_schema");
+        assert_eq!(res.tables.len(), 3);
+        let bar = res.table("my_schema", "bar").expect("table bar");
+        assert_eq!(bar.engine.as_deref(), Some("InnoDB"));
+        assert_eq!(bar.primary_key, vec!["id"]);
+        assert_eq!(bar.columns[0], ColumnInfo { name: String::from("id"), data_type: String::from("bigint"), column_type: String::from("bigint(20)"), ..Default::default() });
+        assert_eq!(bar.columns[1].column_type, "varchar(255)");
+        assert_eq!(bar.columns[1].charset.as_deref(), Some("latin1"));
+        assert!(bar.columns[1].collation.is_none());
+        assert_eq!(bar.indexes, vec![IndexInfo { name: String::from("PRIMARY"), unique: true, columns: vec![String::from("id")] }]);
+
+        assert_eq!(res.fks.len(), 2);
+        assert_eq!(res.fks[1].name, "baz_ibfk_2");
+        assert_eq!(res.fks[1].schema, "my_schema");
+        assert_eq!(res.fks[1].table, "baz");
+        assert_eq!(res.fks[1].columns, vec!["bar_id"]);
+        assert_eq!(res.fks[1].ref_table, "bar");
+        assert_eq!(res.fks[1].ref_columns, vec!["id"]);
+        assert_eq!(res.fks[1].on_delete, FkAction::Restrict);
+        assert!(!res.fks[1].is_virtual);
+    }
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = r"
+            USE shop;
+            CREATE TABLE IF NOT EXISTS line (
+                order_id INT UNSIGNED NOT NULL,
+                shop_id int NOT NULL,
+                product_id INT REFERENCES product (id) ON DELETE SET NULL,
+                status ENUM('a','b') CHARACTER SET latin1 DEFAULT 'a',
+                code varchar(10) COLLATE utf8mb4_bin,
+                PRIMARY KEY USING BTREE (order_id, shop_id),
+                UNIQUE KEY uk (product_id),
+                KEY idx ((order_id + 1)),
+                CONSTRAINT CHECK (shop_id > 0),
+                CONSTRAINT fk_order FOREIGN KEY idx_order (order_id, shop_id) REFERENCES `order` (id, shop_id) ON UPDATE CASCADE ON DELETE CASCADE,
+                FOREIGN KEY (shop_id) REFERENCES other.shop (id) MATCH FULL
+            ) ENGINE = MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;
+            ALTER TABLE line ADD CONSTRAINT fk_late FOREIGN KEY (status) REFERENCES statuses (code), ADD INDEX (status);
+            ALTER TABLE line ADD FOREIGN KEY (code) REFERENCES codes (code);
+        ";
+        let res = parse(sql, "default");
+        let line = res.table("shop", "line").expect("table line");
+        assert_eq!(line.engine.as_deref(), Some("MyISAM"));
+        assert!(!line.partitioned);
+        assert_eq!(line.primary_key, vec!["order_id", "shop_id"]);
+        assert_eq!(line.columns.len(), 5);
+        assert_eq!(line.columns[0].column_type, "int unsigned");
+        assert_eq!(line.columns[3].column_type, "enum('a','b')");
+        assert_eq!(line.columns[3].charset.as_deref(), Some("latin1"));
+        assert!(line.columns[3].collation.is_none());
+        assert_eq!(line.columns[4].charset.as_deref(), Some("utf8mb4"));
+        assert_eq!(line.columns[4].collation.as_deref(), Some("utf8mb4_bin"));
+        assert!(line.columns[0].charset.is_none());
+        let indexes: Vec<(&str, bool)> = line.indexes.iter().map(|idx| (idx.name.as_str(), idx.unique)).collect();
+        assert_eq!(indexes, vec![("PRIMARY", true), ("uk", true)]);
+
+        assert_eq!(res.fks.len(), 5);
+        assert_eq!(res.fks[0].name, "line_ibfk_1");
+        assert_eq!(res.fks[0].columns, vec!["product_id"]);
+        assert_eq!(res.fks[0].on_delete, FkAction::SetNull);
+
+        assert_eq!(res.fks[1].name, "fk_order");
+        assert_eq!(res.fks[1].columns, vec!["order_id", "shop_id"]);
+        assert_eq!(res.fks[1].ref_table, "order");
+        assert_eq!(res.fks[1].ref_columns, vec!["id", "shop_id"]);
+        assert_eq!(res.fks[1].on_delete, FkAction::Cascade);
+
+        assert_eq!(res.fks[2].name, "line_ibfk_2");
+        assert_eq!(res.fks[2].ref_schema, "other");
+        assert_eq!(res.fks[2].ref_table, "shop");
+
+        assert_eq!(res.fks[3].name, "fk_late");
+        assert_eq!(res.fks[3].schema, "shop");
+        assert_eq!(res.fks[3].ref_columns, vec!["code"]);
+        // Numbered after the unnamed FKs of CREATE TABLE
+        assert_eq!(res.fks[4].name, "line_ibfk_3");
+
+        let res = parse("CREATE TABLE event (id int, day date) ENGINE=InnoDB PARTITION BY RANGE (YEAR(day)) (PARTITION p0 VALUES LESS THAN (2020));", "sch");
+        assert!(res.tables[0].partitioned);
+        assert!(!res.tables[0].enforces_fks());
+    }
+}
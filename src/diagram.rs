@@ -0,0 +1,194 @@
+//! Render the FK graph as a Graphviz DOT or Mermaid ER diagram
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::fk::{FkInfo, FkIndex};
+use crate::report::{Report, ViolationKind};
+
+/// Output format of the diagram, deduced from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramFormat {
+    /// .dot or .gv
+    Dot,
+    /// .mmd or .mermaid
+    Mermaid,
+}
+
+impl DiagramFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "dot" | "gv" => Some(DiagramFormat::Dot),
+            "mmd" | "mermaid" => Some(DiagramFormat::Mermaid),
+            _ => None,
+        }
+    }
+}
+
+/// Render the diagram. If a report is given, edges with violations show their orphan count.
+pub fn render(format: DiagramFormat, fk_idx: &FkIndex, report: Option<&Report>) -> String {
+    match format {
+        DiagramFormat::Dot => to_dot(fk_idx, report),
+        DiagramFormat::Mermaid => to_mermaid(fk_idx, report),
+    }
+}
+
+/// Tables are qualified with their schema only if the index spans several schemas
+struct Naming {
+    qualified: bool,
+}
+
+impl Naming {
+    fn new(fk_idx: &FkIndex) -> Self {
+        let schemas: BTreeSet<&str> = fk_idx.fks.iter()
+            .flat_map(|fk| [fk.schema.as_str(), fk.ref_schema.as_str()])
+            .collect();
+        Naming { qualified: schemas.len() > 1 }
+    }
+
+    fn child(&self, fk: &FkInfo) -> String {
+        self.name(&fk.schema, &fk.table)
+    }
+
+    fn parent(&self, fk: &FkInfo) -> String {
+        self.name(&fk.ref_schema, &fk.ref_table)
+    }
+
+    fn name(&self, schema: &str, table: &str) -> String {
+        if self.qualified {
+            format!("{schema}.{table}")
+        } else {
+            table.to_string()
+        }
+    }
+}
+
+/// Number of rows referencing a missing row, the other kinds of violation are not orphans
+fn orphans(fk: &FkInfo, report: Option<&Report>) -> usize {
+    report.map(|r| r.violations.iter()
+            .filter(|v| v.constraint == fk.name && v.kind == ViolationKind::MissingParent)
+            .map(|v| v.count)
+            .sum())
+        .unwrap_or(0)
+}
+
+/// Graphviz DOT: edges go from the table to the referenced table.
+/// Virtual FKs are dashed, cross-schema FKs blue, self references grey, and FKs with violations red.
+pub fn to_dot(fk_idx: &FkIndex, report: Option<&Report>) -> String {
+    let naming = Naming::new(fk_idx);
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+    let mut res = String::from("digraph fk {\n    rankdir=LR;\n    node [shape=box];\n");
+    let tables: BTreeSet<String> = fk_idx.fks.iter()
+        .flat_map(|fk| [naming.child(fk), naming.parent(fk)])
+        .collect();
+    for table in tables.iter() {
+        res += &format!("    \"{}\";\n", escape(table));
+    }
+
+    for fk in fk_idx.fks.iter() {
+        let mut label = format!("{}\\n{} -> {}", escape(&fk.name), escape(&fk.columns.join(", ")), escape(&fk.ref_columns.join(", ")));
+        let mut attrs = Vec::new();
+        if fk.is_virtual {
+            attrs.push(String::from("style=dashed"));
+        }
+        if fk.is_cross_schema() {
+            attrs.push(String::from("color=blue"));
+        } else if fk.is_self_referencing() {
+            attrs.push(String::from("color=grey40"));
+        }
+        let count = orphans(fk, report);
+        if count > 0 {
+            label += &format!("\\n{count} orphans");
+            attrs.push(String::from("color=red, fontcolor=red, penwidth=2"));
+        }
+        attrs.insert(0, format!("label=\"{label}\""));
+        res += &format!("    \"{}\" -> \"{}\" [{}];\n", escape(&naming.child(fk)), escape(&naming.parent(fk)), attrs.join(", "));
+    }
+    res += "}\n";
+    res
+}
+
+/// Mermaid ER diagram: one relationship per FK, from the referenced table to the table.
+/// Virtual FKs are non-identifying (dotted), other styles are given in the label since Mermaid can't color edges.
+pub fn to_mermaid(fk_idx: &FkIndex, report: Option<&Report>) -> String {
+    let naming = Naming::new(fk_idx);
+    // Mermaid entity names can't contain dots
+    let entity = |name: String| name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "-");
+
+    let mut res = String::from("erDiagram\n");
+    for fk in fk_idx.fks.iter() {
+        let line = if fk.is_virtual { ".." } else { "--" };
+        let mut label = format!("{} ({})", fk.name, fk.columns.join(", "));
+        if fk.is_cross_schema() {
+            label += " cross-schema";
+        } else if fk.is_self_referencing() {
+            label += " self";
+        }
+        let count = orphans(fk, report);
+        if count > 0 {
+            label += &format!(" !! {count} orphans");
+        }
+        res += &format!("    {} ||{}o{{ {} : \"{}\"\n", entity(naming.parent(fk)), line, entity(naming.child(fk)), label.replace('"', "'"));
+    }
+    res
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use mysql::Value;
+
+    fn fk(name: &str, table: &str, column: &str, ref_table: &str) -> FkInfo {
+        FkInfo::new((Value::from(name), Value::from("shop"), Value::from(table), Value::from(column), Value::from(ref_table), Value::from("id")))
+    }
+
+    fn fks() -> Vec<FkInfo> {
+        let mut virtual_fk = fk("vfk", "line", "col", "product");
+        virtual_fk.is_virtual = true;
+        vec![fk("fk1", "line", "col", "order"), fk("fk2", "category", "col", "category"), virtual_fk]
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(DiagramFormat::from_path(&PathBuf::from("a/graph.DOT")), Some(DiagramFormat::Dot));
+        assert_eq!(DiagramFormat::from_path(&PathBuf::from("graph.mmd")), Some(DiagramFormat::Mermaid));
+        assert_eq!(DiagramFormat::from_path(&PathBuf::from("graph.png")), None);
+        assert_eq!(DiagramFormat::from_path(&PathBuf::from("graph")), None);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let idx = FkIndex::from(fks());
+        let mut report = Report::default();
+        report.add(&idx.fks[0], ViolationKind::MissingParent, 2);
+        report.add(&idx.fks[1], ViolationKind::Cycle, 4);
+
+        let res = to_dot(&idx, Some(&report));
+        assert!(res.starts_with("digraph fk {\n"));
+        assert!(res.contains("    \"category\";\n"));
+        assert!(res.contains("    \"line\" -> \"order\" [label=\"fk1\\ncol -> id\\n2 orphans\", color=red, fontcolor=red, penwidth=2];\n"));
+        assert!(res.contains("    \"category\" -> \"category\" [label=\"fk2\\ncol -> id\", color=grey40];\n"));
+        assert!(res.contains("    \"line\" -> \"product\" [label=\"vfk\\ncol -> id\", style=dashed];\n"));
+        assert!(res.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mut cross = fk("fk3", "order", "user_id", "user");
+        cross.ref_schema = String::from("auth");
+        let mut fks = fks();
+        fks.push(cross);
+        let idx = FkIndex::from(fks);
+
+        let res = to_mermaid(&idx, None);
+        assert!(res.starts_with("erDiagram\n"));
+        assert!(res.contains("    shop-order ||--o{ shop-line : \"fk1 (col)\"\n"));
+        assert!(res.contains("    shop-category ||--o{ shop-category : \"fk2 (col) self\"\n"));
+        assert!(res.contains("    shop-product ||..o{ shop-line : \"vfk (col)\"\n"));
+        assert!(res.contains("    auth-user ||--o{ shop-order : \"fk3 (user_id) cross-schema\"\n"));
+    }
+}
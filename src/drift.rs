@@ -0,0 +1,117 @@
+//! Schema drift: differences between an expected set of FK constraints and the constraints of the database
+
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::fk::{FkAction, FkIndex, FkInfo};
+
+/// A difference between an expected and an actual constraint
+#[derive(Debug)]
+pub enum Drift {
+    /// Expected, but not in the database
+    Missing(Rc<FkInfo>),
+    /// In the database, but not expected
+    Extra(Rc<FkInfo>),
+    /// Same constraint name, other columns or referenced table
+    ChangedColumns { expected: Rc<FkInfo>, actual: Rc<FkInfo> },
+    ChangedOnDelete { expected: Rc<FkInfo>, actual: Rc<FkInfo> },
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::Missing(fk) => write!(f, "Missing constraint {fk}"),
+            Drift::Extra(fk) => write!(f, "Extra constraint {fk}"),
+            Drift::ChangedColumns { expected, actual } => write!(f, "Changed constraint {}, expected {} referencing table {} {}, found {} referencing table {} {}",
+                actual.name,
+                FkInfo::column_desc(&expected.columns), expected.ref_table, FkInfo::column_desc(&expected.ref_columns),
+                FkInfo::column_desc(&actual.columns), actual.ref_table, FkInfo::column_desc(&actual.ref_columns)),
+            Drift::ChangedOnDelete { expected, actual } => write!(f, "Changed constraint {}, expected ON DELETE {}, found ON DELETE {}",
+                actual.name, expected.on_delete, actual.on_delete),
+        }
+    }
+}
+
+/// Same columns and referenced columns
+fn same_columns(a: &FkInfo, b: &FkInfo) -> bool {
+    a.table == b.table && a.columns == b.columns
+        && a.ref_schema == b.ref_schema && a.ref_table == b.ref_table && a.ref_columns == b.ref_columns
+}
+
+/// RESTRICT and NO ACTION are the same for InnoDB
+fn same_on_delete(a: FkAction, b: FkAction) -> bool {
+    let normalize = |action| if action == FkAction::NoAction { FkAction::Restrict } else { action };
+    normalize(a) == normalize(b)
+}
+
+/// Compare the expected constraints with the actual ones. An expected constraint matches the actual constraint
+/// with the same name, or else the one on the same columns (virtual FKs are usually named differently).
+/// The actual constraints matching none are reported as extra if `report_extra`, not when the expected constraints
+/// are the virtual FKs of a config file, which do not restate the constraints of the database.
+pub fn compare(expected: &FkIndex, actual: &FkIndex, report_extra: bool) -> Vec<Drift> {
+    let mut res = Vec::new();
+    let mut matched: Vec<&str> = Vec::new();
+    // Polymorphic branches and embedded references can not be constraints
+    for exp in expected.fks.iter().filter(|fk| fk.can_be_constraint()) {
+        let found = actual.fks_by_name.get(&exp.name)
+            .filter(|fk| fk.schema == exp.schema)
+            .or_else(|| actual.fks.iter().find(|fk| fk.schema == exp.schema && same_columns(fk, exp)));
+        let Some(act) = found else {
+            res.push(Drift::Missing(exp.clone()));
+            continue;
+        };
+        matched.push(&act.name);
+        if !same_columns(exp, act) {
+            res.push(Drift::ChangedColumns { expected: exp.clone(), actual: act.clone() });
+        } else if !same_on_delete(exp.on_delete, act.on_delete) {
+            res.push(Drift::ChangedOnDelete { expected: exp.clone(), actual: act.clone() });
+        }
+    }
+    for act in actual.fks.iter().filter(|fk| report_extra && !matched.contains(&fk.name.as_str())) {
+        res.push(Drift::Extra(act.clone()));
+    }
+    res
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{compare, Drift};
+    use crate::ddl;
+    use crate::fk::FkIndex;
+
+    #[test]
+    fn test_compare() {
+        let expected = ddl::parse(r"
+            CREATE TABLE a (id int PRIMARY KEY, b_id int, c_id int, d_id int, e_id int,
+                CONSTRAINT fk_b FOREIGN KEY (b_id) REFERENCES b (id) ON DELETE CASCADE,
+                CONSTRAINT fk_c FOREIGN KEY (c_id) REFERENCES c (id),
+                CONSTRAINT vfk_d FOREIGN KEY (d_id) REFERENCES d (id),
+                CONSTRAINT fk_e FOREIGN KEY (e_id) REFERENCES e (id) ON DELETE NO ACTION);", "sch");
+        let actual = ddl::parse(r"
+            CREATE TABLE a (id int PRIMARY KEY, b_id int, c_id int, d_id int, e_id int, f_id int,
+                CONSTRAINT fk_b FOREIGN KEY (b_id) REFERENCES b (id) ON DELETE SET NULL,
+                CONSTRAINT fk_c FOREIGN KEY (c_id, d_id) REFERENCES c (id, d_id),
+                CONSTRAINT a_ibfk_1 FOREIGN KEY (d_id) REFERENCES d (id),
+                CONSTRAINT fk_e FOREIGN KEY (e_id) REFERENCES e (id),
+                CONSTRAINT fk_f FOREIGN KEY (f_id) REFERENCES f (id));", "sch");
+        let res = compare(&FkIndex::from(expected.fks.clone()), &FkIndex::from(actual.fks.clone()), true);
+        let res: Vec<String> = res.iter().map(Drift::to_string).collect();
+        assert_eq!(res, vec![
+            "Changed constraint fk_b, expected ON DELETE CASCADE, found ON DELETE SET NULL",
+            "Changed constraint fk_c, expected column c_id referencing table c column id, found columns (c_id, d_id) referencing table c columns (id, d_id)",
+            "Extra constraint fk_f in schema sch on table a column f_id referencing table f column id",
+        ]);
+        // Expected constraints of a config file
+        let res = compare(&FkIndex::from(expected.fks), &FkIndex::from(actual.fks), false);
+        assert_eq!(res.len(), 2);
+        assert!(!res.iter().any(|d| matches!(d, Drift::Extra(_))));
+
+        let res = compare(&FkIndex::from(Vec::new()), &FkIndex::from(ddl::parse("CREATE TABLE a (b_id int REFERENCES b (id));", "sch").fks), true);
+        assert!(matches!(res[..], [Drift::Extra(_)]));
+        let res = compare(&FkIndex::from(Vec::new()), &FkIndex::from(ddl::parse("CREATE TABLE a (b_id int REFERENCES b (id));", "sch").fks), false);
+        assert!(res.is_empty());
+        let res = compare(&FkIndex::from(ddl::parse("CREATE TABLE a (b_id int REFERENCES b (id));", "sch").fks), &FkIndex::from(Vec::new()), true);
+        assert!(matches!(res[..], [Drift::Missing(_)]));
+    }
+}
@@ -0,0 +1,765 @@
+//! Find invalid foreign references in the data of a mysqldump file, without a server.
+//! The dump is read twice: first to collect the keys of the referenced tables, then to check the referencing rows,
+//! so that the tables can be in any order. The keys of a big table are spilled to disk once over a limit.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::sync::Arc;
+
+use mysql::consts::ColumnType;
+use mysql::{Column, Result, Value};
+
+use crate::datadumper;
+use crate::ddl::{self, DdlSchema, Statement, Statements, Token};
+use crate::fk::{FkIndex, FkInfo};
+use crate::fkchecker::FkChecker;
+use crate::report::{Report, ViolationKind};
+use crate::table::{ColumnInfo, TableInfo};
+
+/// Default number of keys of a referenced table kept in memory
+pub const DEFAULT_MAX_KEYS_IN_MEMORY: usize = 10_000_000;
+
+/// Number of files the keys of a referenced table are spread over once spilled to disk
+const SPILL_BUCKETS: usize = 32;
+
+/// Separates the values of a composite key
+const KEY_SEPARATOR: char = '\u{1f}';
+
+/// A value of an INSERT statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    /// Number, or any other unquoted value, as written
+    Number(String),
+    /// Quoted string, unescaped
+    Str(String),
+}
+
+impl Literal {
+    fn to_value(&self) -> Value {
+        match self {
+            Literal::Null => Value::NULL,
+            Literal::Number(n) => n.parse::<i64>().map(Value::Int)
+                .or_else(|_| n.parse::<u64>().map(Value::UInt))
+                .unwrap_or_else(|_| Value::Bytes(n.clone().into_bytes())),
+            Literal::Str(s) => Value::Bytes(s.clone().into_bytes()),
+        }
+    }
+}
+
+/// Rows of an INSERT statement
+#[derive(Debug, PartialEq)]
+pub struct Insert {
+    pub schema: String,
+    pub table: String,
+    /// Explicit column list (mysqldump --complete-insert)
+    pub columns: Option<Vec<String>>,
+    pub rows: Vec<Vec<Literal>>,
+}
+
+impl Insert {
+    /// Position in the rows of each of `columns`
+    fn positions(&self, table: &TableInfo, columns: &[String]) -> Option<Vec<usize>> {
+        columns.iter()
+            .map(|c| match &self.columns {
+                Some(names) => names.iter().position(|n| n.eq_ignore_ascii_case(c)),
+                None => table.column_index(c),
+            })
+            .collect()
+    }
+
+    /// Values of a row in the order of the table definition, missing columns are NULL
+    fn table_row(&self, table: &TableInfo, row: &[Literal]) -> Vec<Literal> {
+        match &self.columns {
+            None => row.to_vec(),
+            Some(names) => table.columns.iter()
+                .map(|c| names.iter().position(|n| n.eq_ignore_ascii_case(&c.name))
+                    .and_then(|idx| row.get(idx).cloned())
+                    .unwrap_or(Literal::Null))
+                .collect(),
+        }
+    }
+}
+
+const INSERT_MODIFIERS: [&str; 6] = ["INSERT", "REPLACE", "LOW_PRIORITY", "DELAYED", "HIGH_PRIORITY", "IGNORE"];
+
+/// Parse an INSERT ... VALUES or REPLACE ... VALUES statement, `schema` is the current schema (USE statement)
+pub fn parse_insert(stmt: &str, schema: &str) -> Option<Insert> {
+    let head: String = stmt.chars().take(7).collect::<String>().to_uppercase();
+    if !head.starts_with("INSERT") && !head.starts_with("REPLACE") {
+        return None;
+    }
+    let (start, end) = values_keyword(stmt)?;
+    let tokens = ddl::tokenize(&stmt[..start]);
+    let mut pos = tokens.iter()
+        .take_while(|t| matches!(t, Token::Word(w) if INSERT_MODIFIERS.iter().any(|m| w.eq_ignore_ascii_case(m))))
+        .count();
+    if tokens.get(pos).is_some_and(|t| matches!(t, Token::Word(w) if w.eq_ignore_ascii_case("INTO"))) {
+        pos += 1;
+    }
+    let mut table = tokens.get(pos)?.ident()?.to_string();
+    let mut insert_schema = schema.to_string();
+    pos += 1;
+    if tokens.get(pos) == Some(&Token::Sym('.')) {
+        insert_schema = table;
+        table = tokens.get(pos + 1)?.ident()?.to_string();
+        pos += 2;
+    }
+    let columns = match tokens.get(pos) {
+        Some(Token::Sym('(')) => Some(tokens[pos + 1..].iter()
+            .take_while(|t| **t != Token::Sym(')'))
+            .filter_map(|t| t.ident().map(String::from))
+            .collect()),
+        _ => None,
+    };
+    let rows = parse_rows(&stmt[end..])?;
+    Some(Insert { schema: insert_schema, table, columns, rows })
+}
+
+/// Byte range of the VALUES keyword of an INSERT statement
+fn values_keyword(stmt: &str) -> Option<(usize, usize)> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let mut quote: Option<char> = None;
+    for (idx, c) in stmt.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if c == '`' || c == '\'' || c == '"' {
+            quote = Some(c);
+            continue;
+        }
+        if stmt[..idx].chars().next_back().is_some_and(is_ident) {
+            continue;
+        }
+        for kw in ["VALUES", "VALUE"] {
+            let rest = &stmt[idx..];
+            if rest.get(..kw.len()).is_some_and(|w| w.eq_ignore_ascii_case(kw))
+                && !rest[kw.len()..].chars().next().is_some_and(is_ident) {
+                return Some((idx, idx + kw.len()));
+            }
+        }
+    }
+    None
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+/// Parse the tuples of a VALUES clause: (1,'a'),(2,NULL)
+fn parse_rows(values: &str) -> Option<Vec<Vec<Literal>>> {
+    let mut chars = values.chars().peekable();
+    let mut rows = Vec::new();
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next() != Some('(') {
+            return None;
+        }
+        let mut row = Vec::new();
+        loop {
+            skip_whitespace(&mut chars);
+            row.push(parse_literal(&mut chars)?);
+            match chars.next() {
+                Some(',') => continue,
+                Some(')') => break,
+                _ => return None,
+            }
+        }
+        rows.push(row);
+        skip_whitespace(&mut chars);
+        // Anything else than another tuple ends the list, eg: ON DUPLICATE KEY UPDATE
+        if chars.next_if_eq(&',').is_none() {
+            break;
+        }
+    }
+    Some(rows)
+}
+
+/// Parse a value of a tuple, up to the next ',' or ')'
+fn parse_literal(chars: &mut Peekable<Chars>) -> Option<Literal> {
+    if let Some(quote) = chars.next_if(|c| *c == '\'' || *c == '"') {
+        let value = ddl::read_quoted(chars, quote);
+        skip_whitespace(chars);
+        return Some(Literal::Str(value));
+    }
+    let mut text = String::new();
+    let mut string = None;
+    let mut depth = 0usize;
+    while let Some(&c) = chars.peek() {
+        match c {
+            ',' | ')' if depth == 0 => break,
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '\'' | '"' => {
+                chars.next();
+                let value = ddl::read_quoted(chars, c);
+                if depth == 0 && text.starts_with('_') {
+                    // Character set introducer, eg: _binary 'abc'
+                    string = Some(value);
+                } else {
+                    text.push(c);
+                    text.push_str(&value);
+                    text.push(c);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        text.push(c);
+        chars.next();
+    }
+    if let Some(value) = string {
+        return Some(Literal::Str(value));
+    }
+    match text.trim() {
+        "" => None,
+        t if t.eq_ignore_ascii_case("NULL") => Some(Literal::Null),
+        t => Some(Literal::Number(t.to_string())),
+    }
+}
+
+/// Types compared by value
+const NUMERIC_TYPES: [&str; 13] = ["tinyint", "smallint", "mediumint", "int", "integer", "bigint", "decimal", "numeric", "dec", "float", "double", "real", "year"];
+
+/// How MySQL compares the values of a column, from its type and collation
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Comparison {
+    /// Numbers by value, eg: '01' = 1.0
+    numeric: bool,
+    /// Case insensitive collation (_ci)
+    fold_case: bool,
+    /// PAD SPACE collation, trailing spaces are ignored
+    strip_padding: bool,
+}
+
+impl Comparison {
+    /// Values of an unknown column are compared as they are
+    fn of(column: Option<&ColumnInfo>) -> Self {
+        let Some(column) = column else {
+            return Self::default();
+        };
+        let data_type = column.data_type.to_lowercase();
+        if NUMERIC_TYPES.contains(&data_type.as_str()) {
+            return Comparison { numeric: true, ..Self::default() };
+        }
+        if column.charset.is_none() && column.collation.is_none() && !data_type.contains("char") && !data_type.contains("text") {
+            return Self::default();
+        }
+        // Without collation in the DDL, the default one of the server: case insensitive, and NO PAD for MySQL 8
+        match column.collation.as_deref().map(str::to_lowercase) {
+            // The NO PAD collations are the UCA 9.0.0 ones of MySQL 8 and the _nopad_ ones of MariaDB
+            Some(collation) => Comparison {
+                numeric: false,
+                fold_case: collation.ends_with("_ci"),
+                strip_padding: !collation.contains("_0900_") && !collation.contains("_nopad_"),
+            },
+            None => Comparison { numeric: false, fold_case: true, strip_padding: false },
+        }
+    }
+
+    /// Comparison of each of `columns` of `table`
+    fn of_columns(table: &TableInfo, columns: &[String]) -> Vec<Self> {
+        columns.iter().map(|c| Self::of(table.column(c))).collect()
+    }
+
+    /// A value as MySQL compares it, None for NULL
+    fn value(&self, literal: &Literal) -> Option<String> {
+        let v = match literal {
+            Literal::Null => return None,
+            Literal::Number(v) | Literal::Str(v) => v,
+        };
+        if self.numeric {
+            return Some(normalize_number(v.trim()).unwrap_or_else(|| v.clone()));
+        }
+        let v = if self.strip_padding { v.trim_end_matches(' ') } else { v };
+        Some(if self.fold_case { v.to_lowercase() } else { v.to_string() })
+    }
+}
+
+/// Key made of the values of `positions` in a row, None if one of them is NULL.
+/// The values are normalized by the `comparisons` of their columns so that keys equal for MySQL are equal, eg: 1, '1' and 1.0
+fn key(row: &[Literal], positions: &[usize], comparisons: &[Comparison]) -> Option<String> {
+    let mut res = String::new();
+    for (i, (&idx, comparison)) in positions.iter().zip(comparisons).enumerate() {
+        if i > 0 {
+            res.push(KEY_SEPARATOR);
+        }
+        res.push_str(&comparison.value(row.get(idx)?)?);
+    }
+    Some(res)
+}
+
+/// Canonical form of a decimal number, eg: "+01.50" gives "1.5", None if `v` is not a decimal number
+fn normalize_number(v: &str) -> Option<String> {
+    let (negative, digits) = match v.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, v.strip_prefix('+').unwrap_or(v)),
+    };
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    if (int.is_empty() && frac.is_empty()) || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (int, frac) = (int.trim_start_matches('0'), frac.trim_end_matches('0'));
+    let mut res = String::new();
+    if negative && !(int.is_empty() && frac.is_empty()) {
+        res.push('-');
+    }
+    res.push_str(if int.is_empty() { "0" } else { int });
+    if !frac.is_empty() {
+        res.push('.');
+        res.push_str(frac);
+    }
+    Some(res)
+}
+
+/// Escape tabs and new lines, so that a value fits in a field of a spill file line
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => res.push('\t'),
+            Some('n') => res.push('\n'),
+            Some('r') => res.push('\r'),
+            Some(e) => res.push(e),
+            None => {}
+        }
+    }
+    res
+}
+
+fn encode_row(row: &[Literal]) -> String {
+    row.iter()
+        .map(|v| match v {
+            Literal::Null => String::from("N"),
+            Literal::Number(n) => format!("#{}", escape(n)),
+            Literal::Str(s) => format!("'{}", escape(s)),
+        })
+        .collect::<Vec<String>>()
+        .join("\t")
+}
+
+fn decode_row(line: &str) -> Vec<Literal> {
+    line.split('\t')
+        .map(|field| match field.split_at(field.len().min(1)) {
+            ("#", n) => Literal::Number(unescape(n)),
+            ("'", s) => Literal::Str(unescape(s)),
+            _ => Literal::Null,
+        })
+        .collect()
+}
+
+fn bucket(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % SPILL_BUCKETS as u64) as usize
+}
+
+/// Bucket files of a spilled key set: p<N> hold the keys, c<N> the referencing rows to check against them
+struct Spill {
+    dir: PathBuf,
+    parents: Vec<BufWriter<File>>,
+    children: Vec<BufWriter<File>>,
+}
+
+impl Spill {
+    fn create(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let parents = (0..SPILL_BUCKETS)
+            .map(|b| File::create(dir.join(format!("p{b}"))).map(BufWriter::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Spill { dir, parents, children: Vec::new() })
+    }
+
+    fn add_parent(&mut self, key: &str) -> io::Result<()> {
+        writeln!(self.parents[bucket(key)], "{}", escape(key))
+    }
+
+    fn add_child(&mut self, fk_pos: usize, key: &str, row: &[Literal]) -> io::Result<()> {
+        if self.children.is_empty() {
+            // All keys are known, close the key files before opening the row files
+            for mut out in self.parents.drain(..) {
+                out.flush()?;
+            }
+            self.children = (0..SPILL_BUCKETS)
+                .map(|b| File::create(self.dir.join(format!("c{b}"))).map(BufWriter::new))
+                .collect::<io::Result<Vec<_>>>()?;
+        }
+        writeln!(self.children[bucket(key)], "{fk_pos}\t{}\t{}", escape(key), encode_row(row))
+    }
+
+    /// Call `orphan` for each row whose key is not a known key, bucket by bucket
+    fn resolve(mut self, mut orphan: impl FnMut(usize, Vec<Literal>) -> Result<()>) -> Result<()> {
+        for mut out in self.parents.drain(..).chain(self.children.drain(..)) {
+            out.flush()?;
+        }
+        for b in 0..SPILL_BUCKETS {
+            let children = self.dir.join(format!("c{b}"));
+            if !children.exists() {
+                break;
+            }
+            let mut keys = HashSet::new();
+            for line in BufReader::new(File::open(self.dir.join(format!("p{b}")))?).lines() {
+                keys.insert(unescape(&line?));
+            }
+            for line in BufReader::new(File::open(children)?).lines() {
+                let line = line?;
+                let mut fields = line.splitn(3, '\t');
+                let fk_pos = fields.next().and_then(|p| p.parse().ok()).unwrap_or_default();
+                let key = unescape(fields.next().unwrap_or_default());
+                if !keys.contains(&key) {
+                    orphan(fk_pos, decode_row(fields.next().unwrap_or_default()))?;
+                }
+            }
+        }
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// Keys of the referenced columns of a table, in memory up to a limit, then spilled to disk
+struct KeySet {
+    keys: HashSet<String>,
+    spill: Option<Spill>,
+}
+
+impl KeySet {
+    fn insert(&mut self, key: String, max_keys: usize, spill_dir: impl FnOnce() -> PathBuf) -> io::Result<()> {
+        if let Some(spill) = &mut self.spill {
+            return spill.add_parent(&key);
+        }
+        self.keys.insert(key);
+        if self.keys.len() > max_keys {
+            let mut spill = Spill::create(spill_dir())?;
+            for key in self.keys.drain() {
+                spill.add_parent(&key)?;
+            }
+            self.keys.shrink_to_fit();
+            self.spill = Some(spill);
+        }
+        Ok(())
+    }
+}
+
+/// Map a DDL data type to the column type of the protocol, for the CSV dump
+fn column_type(data_type: &str) -> ColumnType {
+    match data_type {
+        "tinyint" | "bool" | "boolean" => ColumnType::MYSQL_TYPE_TINY,
+        "smallint" => ColumnType::MYSQL_TYPE_SHORT,
+        "mediumint" => ColumnType::MYSQL_TYPE_INT24,
+        "int" | "integer" => ColumnType::MYSQL_TYPE_LONG,
+        "bigint" => ColumnType::MYSQL_TYPE_LONGLONG,
+        "decimal" | "numeric" => ColumnType::MYSQL_TYPE_NEWDECIMAL,
+        "float" => ColumnType::MYSQL_TYPE_FLOAT,
+        "double" | "real" => ColumnType::MYSQL_TYPE_DOUBLE,
+        "bit" => ColumnType::MYSQL_TYPE_BIT,
+        "date" => ColumnType::MYSQL_TYPE_DATE,
+        "datetime" => ColumnType::MYSQL_TYPE_DATETIME,
+        "timestamp" => ColumnType::MYSQL_TYPE_TIMESTAMP,
+        "time" => ColumnType::MYSQL_TYPE_TIME,
+        "year" => ColumnType::MYSQL_TYPE_YEAR,
+        "char" | "binary" | "enum" | "set" => ColumnType::MYSQL_TYPE_STRING,
+        "tinyblob" => ColumnType::MYSQL_TYPE_TINY_BLOB,
+        "mediumblob" => ColumnType::MYSQL_TYPE_MEDIUM_BLOB,
+        "longblob" => ColumnType::MYSQL_TYPE_LONG_BLOB,
+        "blob" | "tinytext" | "text" | "mediumtext" | "longtext" => ColumnType::MYSQL_TYPE_BLOB,
+        "json" => ColumnType::MYSQL_TYPE_JSON,
+        "geometry" | "point" | "linestring" | "polygon" => ColumnType::MYSQL_TYPE_GEOMETRY,
+        _ => ColumnType::MYSQL_TYPE_VAR_STRING,
+    }
+}
+
+fn table_columns(table: &TableInfo) -> Arc<[Column]> {
+    table.columns.iter()
+        .map(|c| {
+            let col = Column::new(column_type(&c.data_type))
+                .with_table(table.name.as_bytes())
+                .with_name(c.name.as_bytes());
+            // bit(1) is dumped as a boolean
+            if c.column_type == "bit(1)" { col.with_column_length(1) } else { col }
+        })
+        .collect()
+}
+
+/// Invalid rows found for an FK
+#[derive(Default)]
+struct Orphans {
+    count: usize,
+    out: Option<Box<dyn Write>>,
+}
+
+/// Checks the FKs of an index against the INSERT statements of a dump
+pub struct DumpChecker<'a> {
+    ddl: &'a DdlSchema,
+    fk_idx: &'a FkIndex,
+    checker: &'a FkChecker,
+    max_keys: usize,
+    spill_dir: PathBuf,
+    /// Key sets by referenced (schema, table, columns)
+    parents: HashMap<(String, String, Vec<String>), KeySet>,
+    /// Position in fk_idx.fks of the FKs of each (schema, table)
+    fks_by_table: HashMap<(String, String), Vec<usize>>,
+    /// Indexed like fk_idx.fks
+    orphans: Vec<Orphans>,
+}
+
+impl<'a> DumpChecker<'a> {
+    /// Invalid rows are dumped as configured in `checker`, which must not auto delete
+    pub fn new(ddl: &'a DdlSchema, fk_idx: &'a FkIndex, checker: &'a FkChecker, max_keys: usize) -> Self {
+        let mut parents = HashMap::new();
+        let mut fks_by_table: HashMap<(String, String), Vec<usize>> = HashMap::new();
+        for (pos, fk) in fk_idx.fks.iter().enumerate() {
+            parents.entry(Self::parent_key(fk)).or_insert_with(|| KeySet { keys: HashSet::new(), spill: None });
+            fks_by_table.entry((fk.schema.clone(), fk.table.clone())).or_default().push(pos);
+        }
+        let spill_dir = std::env::temp_dir().join(format!("mysql-fk-fixer-{}", std::process::id()));
+        let orphans = fk_idx.fks.iter().map(|_| Orphans::default()).collect();
+        DumpChecker { ddl, fk_idx, checker, max_keys, spill_dir, parents, fks_by_table, orphans }
+    }
+
+    fn parent_key(fk: &FkInfo) -> (String, String, Vec<String>) {
+        (fk.ref_schema.clone(), fk.ref_table.clone(), fk.ref_columns.clone())
+    }
+
+    /// Read the dump twice and report the rows referencing a missing row.
+    /// `schema` is used for the tables until a USE statement.
+    pub fn check(mut self, path: &Path, schema: &str) -> Result<Report> {
+        self.for_each_insert(path, schema, |this, insert| this.collect_keys(insert))?;
+        self.for_each_insert(path, schema, |this, insert| this.check_rows(insert))?;
+
+        let spilled: Vec<Spill> = self.parents.values_mut()
+            .filter_map(|keys| keys.spill.take())
+            .collect();
+        for spill in spilled {
+            spill.resolve(|fk_pos, row| self.orphan(fk_pos, row))?;
+        }
+
+        let mut report = Report::default();
+        for (fk, orphans) in self.fk_idx.fks.iter().zip(self.orphans.iter_mut()) {
+            if let Some(out) = &mut orphans.out {
+                out.flush()?;
+            }
+            report.add(fk, ViolationKind::MissingParent, orphans.count);
+        }
+        Ok(report)
+    }
+
+    fn for_each_insert(&mut self, path: &Path, schema: &str, mut f: impl FnMut(&mut Self, &Insert) -> Result<()>) -> Result<()> {
+        let mut schema = schema.to_string();
+        for stmt in Statements::new(BufReader::new(File::open(path)?)) {
+            let stmt = stmt?;
+            if let Some(insert) = parse_insert(&stmt, &schema) {
+                f(self, &insert)?;
+            } else if let Statement::Use(name) = ddl::parse_statement(&stmt, &schema) {
+                schema = name;
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_keys(&mut self, insert: &Insert) -> Result<()> {
+        let Some(table) = self.ddl.table(&insert.schema, &insert.table) else {
+            return Ok(());
+        };
+        let (max_keys, spill_dir) = (self.max_keys, &self.spill_dir);
+        for (idx, ((schema, name, columns), keys)) in self.parents.iter_mut().enumerate() {
+            if *schema != insert.schema || *name != insert.table {
+                continue;
+            }
+            let Some(positions) = insert.positions(table, columns) else {
+                continue;
+            };
+            let comparisons = Comparison::of_columns(table, columns);
+            for row in insert.rows.iter() {
+                if let Some(key) = key(row, &positions, &comparisons) {
+                    keys.insert(key, max_keys, || spill_dir.join(idx.to_string()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_rows(&mut self, insert: &Insert) -> Result<()> {
+        let Some(table) = self.ddl.table(&insert.schema, &insert.table) else {
+            return Ok(());
+        };
+        let Some(fk_positions) = self.fks_by_table.get(&(insert.schema.clone(), insert.table.clone())).cloned() else {
+            return Ok(());
+        };
+        for fk_pos in fk_positions {
+            let fk = self.fk_idx.fks[fk_pos].clone();
+            // The references embedded in column values are only checked against a live database
+            if fk.is_embedded() {
+                continue;
+            }
+            let Some(positions) = insert.positions(table, &fk.columns) else {
+                continue;
+            };
+            let comparisons = Comparison::of_columns(table, &fk.columns);
+            // A polymorphic branch only covers the rows of its type
+            let discriminator = match &fk.discriminator {
+                Some(d) => match insert.positions(table, std::slice::from_ref(&d.column)) {
+                    Some(pos) => {
+                        let comparison = Comparison::of_columns(table, std::slice::from_ref(&d.column));
+                        let value = comparison[0].value(&Literal::Str(d.value.clone()));
+                        Some((pos, comparison, value))
+                    }
+                    None => continue,
+                },
+                None => None,
+            };
+            for row in insert.rows.iter() {
+                if discriminator.as_ref().is_some_and(|(pos, comparison, value)| key(row, pos, comparison) != *value) {
+                    continue;
+                }
+                let Some(key) = key(row, &positions, &comparisons) else {
+                    continue;
+                };
+                let keys = self.parents.get_mut(&Self::parent_key(&fk)).expect("key set of a referenced table");
+                match &mut keys.spill {
+                    Some(spill) => spill.add_child(fk_pos, &key, &insert.table_row(table, row))?,
+                    None if keys.keys.contains(&key) => {}
+                    None => self.orphan(fk_pos, insert.table_row(table, row))?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Count and dump a row referencing a missing row
+    fn orphan(&mut self, fk_pos: usize, row: Vec<Literal>) -> Result<()> {
+        let fk = &self.fk_idx.fks[fk_pos];
+        let orphans = &mut self.orphans[fk_pos];
+        orphans.count += 1;
+        if !self.checker.dump_invalid_rows {
+            return Ok(());
+        }
+        let Some(table) = self.ddl.table(&fk.schema, &fk.table) else {
+            return Ok(());
+        };
+        let columns = table_columns(table);
+        if orphans.out.is_none() {
+            let mut out = self.checker.dump_output(fk)?;
+            datadumper::dump_columns(&mut out, &columns)?;
+            orphans.out = Some(out);
+        }
+        let values = row.iter().map(Literal::to_value).collect();
+        let mut row = mysql_common::row::new_row(values, columns);
+        datadumper::dump_row(orphans.out.as_mut().expect("dump output"), &mut row)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::ddl;
+
+    #[test]
+    fn test_parse_insert() {
+        let insert = parse_insert("INSERT INTO `baz` VALUES (1,'a,b'),(2,NULL),(-3,'it''s (x)')", "sch").expect("insert");
+        assert_eq!(insert.schema, "sch");
+        assert_eq!(insert.table, "baz");
+        assert!(insert.columns.is_none());
+        assert_eq!(insert.rows, vec![
+            vec![Literal::Number(String::from("1")), Literal::Str(String::from("a,b"))],
+            vec![Literal::Number(String::from("2")), Literal::Null],
+            vec![Literal::Number(String::from("-3")), Literal::Str(String::from("it's (x)"))],
+        ]);
+
+        let insert = parse_insert("replace ignore into other.t (`id`, `data`) values (0x1F, _binary 'ab\\'c') ON DUPLICATE KEY UPDATE id=1", "sch").expect("insert");
+        assert_eq!(insert.schema, "other");
+        assert_eq!(insert.columns, Some(vec![String::from("id"), String::from("data")]));
+        assert_eq!(insert.rows, vec![vec![Literal::Number(String::from("0x1F")), Literal::Str(String::from("ab'c"))]]);
+
+        assert!(parse_insert("INSERT INTO t SELECT * FROM u", "sch").is_none());
+        assert!(parse_insert("CREATE TABLE `values` (id int)", "sch").is_none());
+    }
+
+    #[test]
+    fn test_key() {
+        let column = |data_type: &str, collation: Option<&str>| ColumnInfo {
+            name: String::from("c"),
+            data_type: data_type.to_string(),
+            column_type: data_type.to_string(),
+            charset: collation.map(|c| c.split('_').next().unwrap_or_default().to_string()),
+            collation: collation.map(str::to_string),
+        };
+        let (int, general_ci) = (column("int", None), column("varchar", Some("utf8mb4_general_ci")));
+        let comparisons = [Comparison::of(Some(&int)), Comparison::of(Some(&general_ci))];
+        let number = vec![Literal::Number(String::from("1")), Literal::Str(String::from("Abc"))];
+        for row in [
+            vec![Literal::Str(String::from("1")), Literal::Str(String::from("abc  "))],
+            vec![Literal::Number(String::from("1.0")), Literal::Str(String::from("ABC"))],
+            vec![Literal::Str(String::from("+01.00")), Literal::Str(String::from("abc"))],
+        ] {
+            assert_eq!(key(&row, &[0, 1], &comparisons), key(&number, &[0, 1], &comparisons));
+        }
+        assert_ne!(key(&[Literal::Str(String::from("12"))], &[0], &comparisons), key(&number, &[0], &comparisons));
+        assert_ne!(key(&[Literal::Str(String::from(" abc"))], &[0], &comparisons[1..]), key(&[Literal::Str(String::from("abc"))], &[0], &comparisons[1..]));
+        assert!(key(&[Literal::Null], &[0], &comparisons).is_none());
+
+        // Strings are not numbers, _bin is case sensitive, NO PAD keeps the trailing spaces
+        let same = |column: &ColumnInfo, a: &str, b: &str| {
+            let comparison = Comparison::of(Some(column));
+            comparison.value(&Literal::Str(a.to_string())) == comparison.value(&Literal::Str(b.to_string()))
+        };
+        assert!(!same(&general_ci, "01", "1"));
+        assert!(!same(&column("varchar", Some("utf8mb4_bin")), "abc", "ABC"));
+        assert!(same(&column("varchar", Some("utf8mb4_bin")), "abc", "abc "));
+        assert!(!same(&column("varchar", Some("utf8mb4_0900_ai_ci")), "abc", "abc "));
+        assert!(same(&column("varchar", Some("utf8mb4_0900_ai_ci")), "abc", "ABC"));
+        assert!(!same(&column("varbinary", None), "abc", "ABC"));
+        assert_eq!(normalize_number("-0.0").as_deref(), Some("0"));
+        assert_eq!(normalize_number(".50").as_deref(), Some("0.5"));
+        assert!(normalize_number("0x1F").is_none());
+        assert!(normalize_number(".").is_none());
+    }
+
+    #[test]
+    fn test_encode_row() {
+        let row = vec![Literal::Null, Literal::Number(String::from("12")), Literal::Str(String::from("a\tb\\n\nc")), Literal::Str(String::new())];
+        assert_eq!(decode_row(&encode_row(&row)), row);
+    }
+
+    fn check_test_dump(max_keys: usize) -> Report {
+        let path = Path::new("tests/invalid_foreign_ref.sql");
+        let mut ddl = ddl::parse_file(path, "my_schema").expect("parse dump");
+        let fk_idx = FkIndex::from(std::mem::take(&mut ddl.fks));
+        let checker = FkChecker::new(false, false, None).expect("checker");
+        DumpChecker::new(&ddl, &fk_idx, &checker, max_keys).check(path, "my_schema").expect("check dump")
+    }
+
+    #[test]
+    fn test_check_dump() {
+        // baz references foo 1 to 3 and bar 1 to 3, bar only has 2 rows
+        let report = check_test_dump(DEFAULT_MAX_KEYS_IN_MEMORY);
+        assert_eq!(report.count("baz_ibfk_1"), 0);
+        assert_eq!(report.count("baz_ibfk_2"), 1);
+
+        // Same result with the keys spilled to disk
+        let report = check_test_dump(1);
+        assert_eq!(report.count("baz_ibfk_1"), 0);
+        assert_eq!(report.count("baz_ibfk_2"), 1);
+    }
+}
@@ -0,0 +1,523 @@
+//! References embedded in column values, eg: the ids of a JSON array `tags` holding `[12, 57]`,
+//! or of a delimited list `role_ids` holding '3,7,12'.
+//! MySQL can not check them: each dangling reference is reported with the primary key of its row,
+//! and can be removed from the value instead of deleting the row.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{self, ErrorKind};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use mysql::prelude::Queryable;
+use mysql::{from_value, Conn, Result, Row, Value};
+use serde_json::Value as JsonValue;
+
+use crate::fk::FkInfo;
+use crate::hierarchy::values_desc;
+use crate::table::TableInfo;
+use crate::utils::key_str;
+
+/// Maximum number of values checked by one query
+const BATCH_SIZE: usize = 1000;
+
+/// Step of a JSON path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// .key or ."quoted key"
+    Key(String),
+    /// [2]
+    Index(usize),
+    /// [*], each element of an array
+    Wildcard,
+}
+
+/// A JSON path to the references of a document, eg: $[*], $.owner.id or $.items[*].product_id.
+/// Only one [*] wildcard is allowed, so that each reference has a position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = |reason: &str| format!("Invalid JSON path {s}: {reason}");
+        let rest = s.trim().strip_prefix('$').ok_or_else(|| err("must start with $"))?;
+        let mut chars = rest.chars().peekable();
+        let mut segments = Vec::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    let mut key = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => key.extend(chars.next()),
+                            Some(c) => key.push(c),
+                            None => return Err(err("unterminated key")),
+                        }
+                    }
+                    segments.push(Segment::Key(key));
+                }
+                '.' => {
+                    let mut key = String::new();
+                    while let Some(&c) = chars.peek().filter(|c| **c != '.' && **c != '[') {
+                        key.push(c);
+                        chars.next();
+                    }
+                    if key.is_empty() || key.contains('*') {
+                        return Err(err("expected a key after ."));
+                    }
+                    segments.push(Segment::Key(key));
+                }
+                '[' => {
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => index.push(c),
+                            None => return Err(err("unterminated [")),
+                        }
+                    }
+                    match index.trim() {
+                        "*" => segments.push(Segment::Wildcard),
+                        n => segments.push(Segment::Index(n.parse().map_err(|_| err("expected an array index or *"))?)),
+                    }
+                }
+                c if c.is_whitespace() => {}
+                c => return Err(err(&format!("unexpected character {c}"))),
+            }
+        }
+        if segments.iter().filter(|s| **s == Segment::Wildcard).count() > 1 {
+            return Err(err("only one [*] wildcard is allowed"));
+        }
+        Ok(JsonPath { segments })
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "${}", render(&self.segments, None))
+    }
+}
+
+/// The segments as a path without the leading $, the wildcard being replaced by `index` if set
+fn render(segments: &[Segment], index: Option<usize>) -> String {
+    let mut res = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Key(key) if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !key.starts_with(|c: char| c.is_ascii_digit()) => {
+                res += &format!(".{key}");
+            }
+            Segment::Key(key) => res += &format!(".\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\"")),
+            Segment::Index(i) => res += &format!("[{i}]"),
+            Segment::Wildcard => match index {
+                Some(i) => res += &format!("[{i}]"),
+                None => res += "[*]",
+            },
+        }
+    }
+    res
+}
+
+impl JsonPath {
+    fn wildcard(&self) -> Option<usize> {
+        self.segments.iter().position(|s| *s == Segment::Wildcard)
+    }
+
+    /// Path of the reference at position `index` of the wildcard array, eg: $[1] for $[*]
+    pub fn concrete(&self, index: Option<usize>) -> String {
+        format!("${}", render(&self.segments, index))
+    }
+
+    /// Row path and column path of JSON_TABLE: one row per element of the wildcard array,
+    /// so that the ordinality of a row is the position of its element
+    fn table_paths(&self) -> (String, String) {
+        match self.wildcard() {
+            Some(pos) => (format!("${}", render(&self.segments[..=pos], None)), format!("${}", render(&self.segments[pos + 1..], None))),
+            None => (String::from("$"), self.to_string()),
+        }
+    }
+
+    /// The values found at the path in a document, with their position in the wildcard array
+    pub fn extract<'v>(&self, doc: &'v JsonValue) -> Vec<(Option<usize>, &'v JsonValue)> {
+        let mut res = Vec::new();
+        walk(&self.segments, doc, None, &mut res);
+        res
+    }
+}
+
+fn walk<'v>(segments: &[Segment], value: &'v JsonValue, index: Option<usize>, res: &mut Vec<(Option<usize>, &'v JsonValue)>) {
+    match segments.split_first() {
+        None => res.push((index, value)),
+        Some((Segment::Key(key), rest)) => {
+            if let Some(v) = value.get(key.as_str()) {
+                walk(rest, v, index, res);
+            }
+        }
+        Some((Segment::Index(i), rest)) => {
+            if let Some(v) = value.get(*i) {
+                walk(rest, v, index, res);
+            }
+        }
+        Some((Segment::Wildcard, rest)) => {
+            for (i, v) in value.as_array().into_iter().flatten().enumerate() {
+                walk(rest, v, Some(i), res);
+            }
+        }
+    }
+}
+
+/// Text of a referenced value, None for null, booleans, arrays and objects which can not reference a row
+fn scalar_text(value: &JsonValue) -> Option<String> {
+    match value {
+        JsonValue::Number(n) => Some(n.to_string()),
+        JsonValue::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// How the references are embedded in the value of the column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Embedding {
+    /// At a path of a JSON document
+    Json(JsonPath),
+    /// Elements of a delimited list, eg: '3,7,12'
+    Delimited(String),
+}
+
+impl Embedding {
+    /// The embedding of a constraint, a JSON document if it has no delimiter
+    pub fn of(fk: &FkInfo) -> io::Result<Self> {
+        match &fk.delimiter {
+            Some(delimiter) => Ok(Embedding::Delimited(delimiter.clone())),
+            None => {
+                let path = fk.json_path.as_deref().unwrap_or("$");
+                Ok(Embedding::Json(path.parse().map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?))
+            }
+        }
+    }
+
+    /// The referenced values of a column value, with their position. Invalid JSON documents hold no reference.
+    fn extract(&self, value: &str) -> Vec<(Option<usize>, String)> {
+        match self {
+            Embedding::Json(path) => match serde_json::from_str::<JsonValue>(value) {
+                Ok(doc) => path.extract(&doc).into_iter()
+                    .filter_map(|(index, v)| scalar_text(v).map(|v| (index, v)))
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            Embedding::Delimited(delimiter) => split_list(value, delimiter).into_iter()
+                .map(|(index, v)| (Some(index), v.to_string()))
+                .collect(),
+        }
+    }
+
+    /// eg: at $[1], element 2
+    fn location(&self, index: Option<usize>) -> String {
+        match self {
+            Embedding::Json(path) => format!("at {}", path.concrete(index)),
+            Embedding::Delimited(_) => format!("element {}", index.unwrap_or_default() + 1),
+        }
+    }
+}
+
+/// The elements of a delimited list, trimmed, with their position. Empty elements are skipped.
+fn split_list<'l>(list: &'l str, delimiter: &str) -> Vec<(usize, &'l str)> {
+    list.split(delimiter)
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, v)| !v.is_empty())
+        .collect()
+}
+
+/// A reference to a missing row
+#[derive(Debug)]
+pub struct DanglingRef {
+    /// Primary key of the row
+    pub key: Vec<Value>,
+    /// Position in the wildcard array or in the list, if any
+    pub index: Option<usize>,
+    /// The referenced value, as text
+    pub value: String,
+}
+
+/// The dangling references of a constraint embedded in column values
+#[derive(Debug)]
+pub struct DanglingRefs {
+    pub fk: Rc<FkInfo>,
+    pub embedding: Embedding,
+    pub primary_key: Vec<String>,
+    pub refs: Vec<DanglingRef>,
+    /// Value of the column of the rows having dangling references in a delimited list, by primary key
+    lists: HashMap<Vec<String>, String>,
+}
+
+impl DanglingRefs {
+    /// eg: Dangling reference 57 in table post row id=3, column tags at $[1], not found in table tag column id
+    pub fn describe(&self, dangling: &DanglingRef) -> String {
+        format!("Dangling reference {} in table {} row {}, column {} {}, not found in table {} {}",
+            dangling.value, self.fk.table, values_desc(&self.primary_key, &dangling.key), self.fk.columns[0],
+            self.embedding.location(dangling.index), self.fk.ref_table, FkInfo::column_desc(&self.fk.ref_columns))
+    }
+
+    /// Primary keys of the rows having dangling references, without duplicates
+    pub fn keys(&self) -> Vec<Vec<Value>> {
+        let mut seen = HashSet::new();
+        self.refs.iter()
+            .filter(|r| seen.insert(key_str(&r.key)))
+            .map(|r| r.key.clone())
+            .collect()
+    }
+
+    /// The dangling references of each row, in order of appearance of the rows
+    fn by_row(&self) -> Vec<(Vec<Value>, Vec<&DanglingRef>)> {
+        let mut res: Vec<(Vec<Value>, Vec<&DanglingRef>)> = Vec::new();
+        let mut by_key: HashMap<Vec<String>, usize> = HashMap::new();
+        for r in self.refs.iter() {
+            let idx = *by_key.entry(key_str(&r.key)).or_insert_with(|| {
+                res.push((r.key.clone(), Vec::new()));
+                res.len() - 1
+            });
+            res[idx].1.push(r);
+        }
+        res
+    }
+}
+
+/// MySQL 8: primary key (k), position (ord) and value of the dangling references, expanded by JSON_TABLE.
+/// The documents which are not valid JSON would fail JSON_TABLE, they are skipped.
+fn json_table_query(fk: &FkInfo, path: &JsonPath, primary_key: &[String]) -> String {
+    let (row_path, column_path) = path.table_paths();
+    format!("SELECT {}, j.ord, j.ref_value FROM {}.{} a \
+        CROSS JOIN JSON_TABLE(a.{}, {} COLUMNS (ord FOR ORDINALITY, ref_value VARCHAR(255) PATH {})) j \
+        LEFT JOIN {}.{} b ON b.{}=j.ref_value WHERE JSON_VALID(a.{}) AND j.ref_value IS NOT NULL AND b.{} IS NULL",
+        primary_key.iter().map(|c| format!("a.{c}")).collect::<Vec<String>>().join(", "),
+        fk.schema, fk.table, fk.columns[0], Value::from(row_path).as_sql(false), Value::from(column_path).as_sql(false),
+        fk.ref_schema, fk.ref_table, fk.ref_columns[0], fk.columns[0], fk.ref_columns[0])
+}
+
+/// Primary key and value of the column of the rows, the references are extracted client side
+fn documents_query(fk: &FkInfo, primary_key: &[String]) -> String {
+    format!("SELECT {}, a.{} FROM {}.{} a WHERE a.{} IS NOT NULL",
+        primary_key.iter().map(|c| format!("a.{c}")).collect::<Vec<String>>().join(", "),
+        fk.columns[0], fk.schema, fk.table, fk.columns[0])
+}
+
+/// The `values` not found in the referenced table, as given
+fn missing_query(fk: &FkInfo, values: &[&String]) -> String {
+    let values = values.iter()
+        .map(|v| format!("SELECT {} AS v", Value::from(v.as_str()).as_sql(false)))
+        .collect::<Vec<String>>()
+        .join(" UNION ALL ");
+    format!("SELECT x.v FROM ({values}) x LEFT JOIN {}.{} b ON b.{}=x.v WHERE b.{} IS NULL",
+        fk.ref_schema, fk.ref_table, fk.ref_columns[0], fk.ref_columns[0])
+}
+
+/// Find the embedded references to missing rows. JSON documents are expanded with JSON_TABLE if the server
+/// supports it (MySQL 8), the other values are read and split client side. The table must have a primary key.
+pub fn dangling_refs(conn: &mut Conn, fk: &Rc<FkInfo>, json_table: bool) -> Result<DanglingRefs> {
+    let embedding = Embedding::of(fk)?;
+    let primary_key = TableInfo::query_primary_key(conn, &fk.schema, &fk.table)?;
+    if primary_key.is_empty() {
+        let msg = format!("Table {} has no primary key, needed to report the references of column {}", fk.table, fk.columns[0]);
+        return Err(io::Error::new(ErrorKind::InvalidData, msg).into());
+    }
+    let mut refs = Vec::new();
+    let mut lists = HashMap::new();
+    match &embedding {
+        Embedding::Json(path) if json_table => {
+            for row in conn.query::<Row, String>(json_table_query(fk, path, &primary_key))? {
+                let mut values = row.unwrap();
+                let value: String = from_value(values.pop().unwrap_or(Value::NULL));
+                let ord: usize = from_value(values.pop().unwrap_or(Value::NULL));
+                let index = path.wildcard().map(|_| ord - 1);
+                refs.push(DanglingRef { key: values, index, value });
+            }
+        }
+        _ => {
+            let mut candidates = Vec::new();
+            for row in conn.query::<Row, String>(documents_query(fk, &primary_key))? {
+                let mut key = row.unwrap();
+                let value: String = from_value(key.pop().unwrap_or(Value::NULL));
+                let found = embedding.extract(&value);
+                if !found.is_empty() && matches!(embedding, Embedding::Delimited(_)) {
+                    lists.insert(key_str(&key), value);
+                }
+                candidates.extend(found.into_iter().map(|(index, value)| DanglingRef { key: key.clone(), index, value }));
+            }
+            let distinct: Vec<&String> = candidates.iter().map(|r| &r.value).collect::<HashSet<&String>>().into_iter().collect();
+            let mut missing: HashSet<String> = HashSet::new();
+            for batch in distinct.chunks(BATCH_SIZE) {
+                missing.extend(conn.query::<String, String>(missing_query(fk, batch))?);
+            }
+            refs = candidates.into_iter().filter(|r| missing.contains(&r.value)).collect();
+            let dangling_rows: HashSet<Vec<String>> = refs.iter().map(|r| key_str(&r.key)).collect();
+            lists.retain(|key, _| dangling_rows.contains(key));
+        }
+    }
+    Ok(DanglingRefs { fk: fk.clone(), embedding, primary_key, refs, lists })
+}
+
+/// The paths to remove from the JSON document of each row, last positions first so that removing one
+/// does not shift the others
+fn removals(dangling: &DanglingRefs, path: &JsonPath) -> Vec<(Vec<Value>, Vec<String>)> {
+    dangling.by_row().into_iter()
+        .map(|(key, mut refs)| {
+            refs.sort_by_key(|r| std::cmp::Reverse(r.index));
+            let mut paths: Vec<String> = refs.iter().map(|r| path.concrete(r.index)).collect();
+            paths.dedup();
+            (key, paths)
+        })
+        .collect()
+}
+
+/// The delimited list of each row, before and after removing its dangling elements and its empty elements
+fn rewrites(dangling: &DanglingRefs, delimiter: &str) -> Vec<(Vec<Value>, String, String)> {
+    dangling.by_row().into_iter()
+        .filter_map(|(key, refs)| {
+            let list = dangling.lists.get(&key_str(&key))?;
+            let kept: Vec<&str> = list.split(delimiter).enumerate()
+                .filter(|(i, v)| !v.trim().is_empty() && !refs.iter().any(|r| r.index == Some(*i)))
+                .map(|(_, v)| v)
+                .collect();
+            Some((key, list.clone(), kept.join(delimiter)))
+        })
+        .collect()
+}
+
+/// Remove the dangling references from the values of their rows, one UPDATE per row.
+/// A delimited list is only rewritten if it did not change since it was read.
+pub fn remove_refs(conn: &mut Conn, dangling: &DanglingRefs) -> Result<()> {
+    let fk = &dangling.fk;
+    let col = &fk.columns[0];
+    let where_key = dangling.primary_key.iter().map(|c| format!("{c}=?")).collect::<Vec<String>>().join(" AND ");
+    match &dangling.embedding {
+        Embedding::Json(path) => {
+            for (key, paths) in removals(dangling, path) {
+                let paths = paths.iter().map(|p| Value::from(p.as_str()).as_sql(false)).collect::<Vec<String>>().join(", ");
+                let query = format!("UPDATE {}.{} SET {col}=JSON_REMOVE({col}, {paths}) WHERE {where_key}", fk.schema, fk.table);
+                conn.exec_drop(query, key)?;
+            }
+        }
+        Embedding::Delimited(delimiter) => {
+            let query = format!("UPDATE {}.{} SET {col}=? WHERE {where_key} AND {col}=?", fk.schema, fk.table);
+            for (key, list, rewritten) in rewrites(dangling, delimiter) {
+                let params: Vec<Value> = std::iter::once(Value::from(rewritten)).chain(key).chain(std::iter::once(Value::from(list))).collect();
+                conn.exec_drop(&query, params)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Delete the rows having dangling references
+pub fn delete_rows(conn: &mut Conn, dangling: &DanglingRefs) -> Result<()> {
+    let fk = &dangling.fk;
+    let where_key = dangling.primary_key.iter().map(|c| format!("{c}=?")).collect::<Vec<String>>().join(" AND ");
+    conn.exec_batch(format!("DELETE FROM {}.{} WHERE {where_key}", fk.schema, fk.table), dangling.keys())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use mysql::Value;
+
+    use super::{documents_query, json_table_query, missing_query, removals, rewrites, split_list, DanglingRef, DanglingRefs, Embedding, JsonPath};
+    use crate::fk::FkInfo;
+
+    fn fk(path: &str) -> FkInfo {
+        FkInfo {
+            json_path: Some(String::from(path)),
+            ..FkInfo::new((Value::from("jref_post_tags"), Value::from("sch"), Value::from("post"), Value::from("tags"), Value::from("tag"), Value::from("id")))
+        }
+    }
+
+    #[test]
+    fn test_json_path() {
+        let path: JsonPath = "$.items[*].\"product id\"".parse().expect("valid path");
+        assert_eq!(path.to_string(), "$.items[*].\"product id\"");
+        assert_eq!(path.concrete(Some(2)), "$.items[2].\"product id\"");
+        assert_eq!(path.table_paths(), (String::from("$.items[*]"), String::from("$.\"product id\"")));
+        let path: JsonPath = "$.owner.id".parse().expect("valid path");
+        assert_eq!(path.table_paths(), (String::from("$"), String::from("$.owner.id")));
+
+        assert!("owner.id".parse::<JsonPath>().is_err());
+        assert!("$[*][*]".parse::<JsonPath>().is_err());
+        assert!("$.*".parse::<JsonPath>().is_err());
+        assert!("$[x]".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn test_extract() {
+        let doc = serde_json::json!({"items": [{"product_id": 12}, {"other": 1}, {"product_id": "ab"}], "owner": {"id": 3}});
+        let path: JsonPath = "$.items[*].product_id".parse().unwrap();
+        let res = path.extract(&doc);
+        assert_eq!(res, vec![(Some(0), &serde_json::json!(12)), (Some(2), &serde_json::json!("ab"))]);
+        let path: JsonPath = "$.owner.id".parse().unwrap();
+        assert_eq!(path.extract(&doc), vec![(None, &serde_json::json!(3))]);
+        let path: JsonPath = "$[*]".parse().unwrap();
+        assert!(path.extract(&doc).is_empty());
+    }
+
+    #[test]
+    fn test_queries() {
+        let fk = fk("$[*]");
+        let path = "$[*]".parse().unwrap();
+        assert_eq!(json_table_query(&fk, &path, &[String::from("id")]), "SELECT a.id, j.ord, j.ref_value FROM sch.post a \
+            CROSS JOIN JSON_TABLE(a.tags, '$[*]' COLUMNS (ord FOR ORDINALITY, ref_value VARCHAR(255) PATH '$')) j \
+            LEFT JOIN sch.tag b ON b.id=j.ref_value WHERE JSON_VALID(a.tags) AND j.ref_value IS NOT NULL AND b.id IS NULL");
+        assert_eq!(documents_query(&fk, &[String::from("id")]), "SELECT a.id, a.tags FROM sch.post a WHERE a.tags IS NOT NULL");
+        let (a, b) = (String::from("12"), String::from("o'k"));
+        assert_eq!(missing_query(&fk, &[&a, &b]), "SELECT x.v FROM (SELECT '12' AS v UNION ALL SELECT 'o\\'k' AS v) x \
+            LEFT JOIN sch.tag b ON b.id=x.v WHERE b.id IS NULL");
+    }
+
+    #[test]
+    fn test_removals() {
+        let path: JsonPath = "$[*]".parse().unwrap();
+        let dangling = DanglingRefs {
+            fk: Rc::new(fk("$[*]")),
+            embedding: Embedding::Json(path.clone()),
+            primary_key: vec![String::from("id")],
+            refs: vec![
+                DanglingRef { key: vec![Value::Int(1)], index: Some(0), value: String::from("12") },
+                DanglingRef { key: vec![Value::Int(2)], index: Some(1), value: String::from("57") },
+                DanglingRef { key: vec![Value::Int(1)], index: Some(3), value: String::from("57") },
+            ],
+            lists: HashMap::new(),
+        };
+        assert_eq!(dangling.describe(&dangling.refs[1]), "Dangling reference 57 in table post row id=2, column tags at $[1], not found in table tag column id");
+        assert_eq!(dangling.keys(), vec![vec![Value::Int(1)], vec![Value::Int(2)]]);
+        assert_eq!(removals(&dangling, &path), vec![
+            (vec![Value::Int(1)], vec![String::from("$[3]"), String::from("$[0]")]),
+            (vec![Value::Int(2)], vec![String::from("$[1]")]),
+        ]);
+    }
+
+    #[test]
+    fn test_delimited() {
+        assert_eq!(split_list("3, 7,,12 ", ","), vec![(0, "3"), (1, "7"), (3, "12")]);
+        let embedding = Embedding::Delimited(String::from(";"));
+        assert_eq!(embedding.extract("4;9"), vec![(Some(0), String::from("4")), (Some(1), String::from("9"))]);
+        assert!(Embedding::Json("$[*]".parse().unwrap()).extract("not json").is_empty());
+
+        let dangling = DanglingRefs {
+            fk: Rc::new(FkInfo { json_path: None, delimiter: Some(String::from(",")), ..fk("$") }),
+            embedding: Embedding::Delimited(String::from(",")),
+            primary_key: vec![String::from("id")],
+            refs: vec![
+                DanglingRef { key: vec![Value::Int(1)], index: Some(1), value: String::from("7") },
+                DanglingRef { key: vec![Value::Int(1)], index: Some(3), value: String::from("12") },
+            ],
+            lists: HashMap::from([(vec![String::from("1")], String::from("3,7,,12"))]),
+        };
+        assert_eq!(dangling.describe(&dangling.refs[1]), "Dangling reference 12 in table post row id=1, column tags element 4, not found in table tag column id");
+        assert_eq!(rewrites(&dangling, ","), vec![(vec![Value::Int(1)], String::from("3,7,,12"), String::from("3"))]);
+    }
+}
@@ -3,35 +3,260 @@
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::rc::Rc;
+use std::str::FromStr;
 
 use mysql::*;
 use mysql::prelude::*;
+use serde::{Deserialize, Serialize};
 
-/// All the needed info to check a Foreign Key
+/// Referential action of an FK (ON DELETE / ON UPDATE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum FkAction {
+    #[default]
+    Restrict,
+    Cascade,
+    SetNull,
+    NoAction,
+    SetDefault,
+}
+
+impl FromStr for FkAction {
+    type Err = String;
+
+    /// Parse a rule as written in DDL or in information_schema, eg: "SET NULL", case insensitive
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let words: Vec<String> = s.split_whitespace().map(|w| w.to_uppercase()).collect();
+        match words.join(" ").as_str() {
+            "RESTRICT" => Ok(FkAction::Restrict),
+            "CASCADE" => Ok(FkAction::Cascade),
+            "SET NULL" => Ok(FkAction::SetNull),
+            "NO ACTION" => Ok(FkAction::NoAction),
+            "SET DEFAULT" => Ok(FkAction::SetDefault),
+            _ => Err(format!("Unknown referential action {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for FkAction {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<FkAction> for String {
+    fn from(value: FkAction) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for FkAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FkAction::Restrict => write!(f, "RESTRICT"),
+            FkAction::Cascade => write!(f, "CASCADE"),
+            FkAction::SetNull => write!(f, "SET NULL"),
+            FkAction::NoAction => write!(f, "NO ACTION"),
+            FkAction::SetDefault => write!(f, "SET DEFAULT"),
+        }
+    }
+}
+
+/// Where the metadata of a constraint comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FkSource {
+    #[default]
+    InformationSchema,
+    /// DDL file or mysqldump (--ddl)
+    Ddl,
+    /// Saved schema snapshot
+    Snapshot,
+    /// Virtual FK of the config file
+    Config,
+}
+
+impl Display for FkSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FkSource::InformationSchema => write!(f, "information_schema"),
+            FkSource::Ddl => write!(f, "DDL file"),
+            FkSource::Snapshot => write!(f, "schema snapshot"),
+            FkSource::Config => write!(f, "config file"),
+        }
+    }
+}
+
+/// Branch of a polymorphic relationship: the reference only applies to the rows whose type column has the value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discriminator {
+    /// Name of the polymorphic relationship
+    pub relationship: String,
+    /// eg: commentable_type
+    pub column: String,
+    /// eg: Post, or App\Models\Post with Laravel
+    pub value: String,
+}
+
+impl Discriminator {
+    /// The value as an escaped SQL string literal
+    pub fn literal(&self) -> String {
+        Value::from(self.value.as_str()).as_sql(false)
+    }
+}
+
+/// Validity period of the referenced rows: the date of the row must be within [valid_from, valid_to)
+/// of a referenced row. A NULL bound is open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validity {
+    /// Column of the table, eg: effective_date
+    pub date_column: String,
+    /// Columns of the referenced table
+    pub valid_from: String,
+    pub valid_to: String,
+}
+
+/// Number of referencing rows required for each referenced row, eg: at least one line per order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cardinality {
+    pub min: u64,
+    pub max: Option<u64>,
+}
+
+impl Display for Cardinality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// All the needed info to check a Foreign Key.
+/// Only the constraint itself is saved in a schema snapshot: the fields skipped by serde are set when
+/// merging the metadata sources, from the config file, so a snapshot must be loaded with the same config.
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FkInfo {
     pub name: String,
     pub schema: String,
     pub table: String,
-    pub column: String,
+    pub columns: Vec<String>,
+    pub ref_schema: String,
     pub ref_table: String,
-    pub ref_column: String,
+    pub ref_columns: Vec<String>,
+    pub on_delete: FkAction,
+    /// Declared in the config file, not enforced by MySQL
+    pub is_virtual: bool,
+    /// Metadata source the FK was found in
+    #[serde(skip)]
+    pub source: FkSource,
+    /// Columns which must also match between the row and the referenced row, eg: tenant_id
+    #[serde(skip)]
+    pub scope_columns: Vec<String>,
+    /// Scope columns of the referenced table, in the same order
+    #[serde(skip)]
+    pub ref_scope_columns: Vec<String>,
+    /// SQL condition on the columns of the referenced table, false for soft-deleted rows, eg: deleted_at IS NULL
+    #[serde(skip)]
+    pub ref_alive: Option<String>,
+    /// Type column and value of the rows covered, for a branch of a polymorphic relationship
+    #[serde(skip)]
+    pub discriminator: Option<Discriminator>,
+    /// JSON path to the references in the document of the column, eg: $[*]
+    #[serde(skip)]
+    pub json_path: Option<String>,
+    /// Delimiter of the list of references in the string of the column, eg: ","
+    #[serde(skip)]
+    pub delimiter: Option<String>,
+    /// Validity period of the referenced rows, the date of the row must be within one of them
+    #[serde(skip)]
+    pub validity: Option<Validity>,
+    /// Number of rows of the table required for each referenced row
+    #[serde(skip)]
+    pub cardinality: Option<Cardinality>,
 }
 
 impl FkInfo {
-    /// Create a new FkInfo from a Row
+    /// Create a new FkInfo from a Row (one column of the constraint), referencing a table in the same schema
     pub fn new(row: (Value, Value, Value, Value, Value, Value)) -> Self {
+        let schema = String::from_value(row.1);
         FkInfo{
             name: String::from_value(row.0),
-            schema: String::from_value(row.1),
+            ref_schema: schema.clone(),
+            schema,
             table: String::from_value(row.2),
-            column: String::from_value(row.3),
+            columns: vec![String::from_value(row.3)],
             ref_table: String::from_value(row.4),
-            ref_column: String::from_value(row.5),
+            ref_columns: vec![String::from_value(row.5)],
+            on_delete: FkAction::default(),
+            is_virtual: false,
+            source: FkSource::default(),
+            scope_columns: Vec::new(),
+            ref_scope_columns: Vec::new(),
+            ref_alive: None,
+            discriminator: None,
+            json_path: None,
+            delimiter: None,
+            validity: None,
+            cardinality: None,
         }
     }
 
+    /// Printable list of the columns, eg: "column foo_id" or "columns (a, b)"
+    pub fn column_desc(columns: &[String]) -> String {
+        if columns.len() == 1 {
+            format!("column {}", columns[0])
+        } else {
+            format!("columns ({})", columns.join(", "))
+        }
+    }
+
+    /// Columns identifying the rows of a violation: the FK columns, plus the type column of a polymorphic branch
+    pub fn key_columns(&self) -> Vec<String> {
+        let mut res = self.columns.clone();
+        if let Some(discriminator) = &self.discriminator {
+            res.push(discriminator.column.clone());
+        }
+        res
+    }
+
+    /// The references are embedded in the value of the column, eg: ids in a JSON document or in a delimited list
+    pub fn is_embedded(&self) -> bool {
+        self.json_path.is_some() || self.delimiter.is_some()
+    }
+
+    /// Can be declared as a constraint in MySQL: neither a polymorphic branch nor embedded references
+    pub fn can_be_constraint(&self) -> bool {
+        self.discriminator.is_none() && !self.is_embedded()
+    }
+
+    /// The referenced table is in another schema
+    pub fn is_cross_schema(&self) -> bool {
+        self.schema != self.ref_schema
+    }
+
+    /// The table references itself (eg: hierarchies)
+    pub fn is_self_referencing(&self) -> bool {
+        self.table == self.ref_table && !self.is_cross_schema()
+    }
+
+    /// Merge the rows of composite constraints (one row per column, ordered by constraint then ordinal position)
+    fn merge_composite(rows: Vec<FkInfo>) -> Vec<FkInfo> {
+        let mut res: Vec<FkInfo> = Vec::with_capacity(rows.len());
+        for fk in rows {
+            match res.last_mut() {
+                Some(last) if last.name == fk.name && last.schema == fk.schema && last.table == fk.table => {
+                    last.columns.extend(fk.columns);
+                    last.ref_columns.extend(fk.ref_columns);
+                }
+                _ => res.push(fk),
+            }
+        }
+        res
+    }
+
     /// Get all the FK constraints using given connection to MySQL (should be using schema information_schema)
     pub fn query_fk_constraints<T>(conn: &mut T, schema: Option<&String>) -> Result<Vec<Self>>
         where T: Queryable
@@ -42,24 +267,81 @@ impl FkInfo {
                 k.CONSTRAINT_SCHEMA,
                 k.TABLE_NAME,
                 k.COLUMN_NAME,
+                k.REFERENCED_TABLE_SCHEMA,
                 k.REFERENCED_TABLE_NAME,
-                k.REFERENCED_COLUMN_NAME
+                k.REFERENCED_COLUMN_NAME,
+                r.DELETE_RULE
             FROM information_schema.KEY_COLUMN_USAGE k
             JOIN information_schema.TABLE_CONSTRAINTS c ON k.CONSTRAINT_NAME=c.CONSTRAINT_NAME AND c.CONSTRAINT_SCHEMA=k.CONSTRAINT_SCHEMA
+            JOIN information_schema.REFERENTIAL_CONSTRAINTS r ON k.CONSTRAINT_NAME=r.CONSTRAINT_NAME AND r.CONSTRAINT_SCHEMA=k.CONSTRAINT_SCHEMA
             WHERE c.CONSTRAINT_TYPE='FOREIGN KEY'");
         if let Some(schema_name) = schema {
             query = format!("{query} AND k.CONSTRAINT_SCHEMA='{schema_name}'");
         }
-        let res = conn.query_map(query, |t| FkInfo::new(t))?;
-    
-        Ok(res)
+        query = format!("{query} ORDER BY k.CONSTRAINT_SCHEMA, k.TABLE_NAME, k.CONSTRAINT_NAME, k.ORDINAL_POSITION");
+        let res = conn.query_map(query, |(name, schema, table, column, ref_schema, ref_table, ref_column, delete_rule)| FkInfo {
+            ref_schema: String::from_value(ref_schema),
+            on_delete: String::from_value(delete_rule).parse().unwrap_or_default(),
+            ..FkInfo::new((name, schema, table, column, ref_table, ref_column))
+        })?;
+
+        Ok(Self::merge_composite(res))
     }
 }
 
 impl Display for FkInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} in schema {} on table {} column {} referencing table {} column {}",
-            self.name, self.schema, self.table, self.column, self.ref_table, self.ref_column)
+        write!(f, "{} in schema {} on table {} {} referencing table ", self.name, self.schema, self.table, Self::column_desc(&self.columns))?;
+        if self.is_cross_schema() {
+            write!(f, "{}.", self.ref_schema)?;
+        }
+        write!(f, "{} {}", self.ref_table, Self::column_desc(&self.ref_columns))?;
+        if !self.scope_columns.is_empty() {
+            write!(f, " within {}", Self::column_desc(&self.scope_columns))?;
+        }
+        if let Some(validity) = &self.validity {
+            write!(f, " valid at {} within [{}, {})", validity.date_column, validity.valid_from, validity.valid_to)?;
+        }
+        if let Some(discriminator) = &self.discriminator {
+            write!(f, " when {} is {}", discriminator.column, discriminator.literal())?;
+        }
+        if let Some(path) = &self.json_path {
+            write!(f, " at JSON path {path}")?;
+        }
+        if let Some(delimiter) = &self.delimiter {
+            write!(f, " as a list delimited by {}", Value::from(delimiter.as_str()).as_sql(false))?;
+        }
+        if self.is_virtual {
+            write!(f, " (virtual)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A polymorphic relationship (eg: commentable_type, commentable_id): the type column tells which table
+/// the columns reference, each type being a branch FK
+#[derive(Debug)]
+pub struct Polymorphic {
+    pub name: String,
+    pub schema: String,
+    pub table: String,
+    pub type_column: String,
+    pub columns: Vec<String>,
+    pub branches: Vec<Rc<FkInfo>>,
+}
+
+impl Polymorphic {
+    /// The known values of the type column
+    pub fn types(&self) -> Vec<&Discriminator> {
+        self.branches.iter().filter_map(|fk| fk.discriminator.as_ref()).collect()
+    }
+}
+
+impl Display for Polymorphic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let types: Vec<&str> = self.types().iter().map(|d| d.value.as_str()).collect();
+        write!(f, "{} in schema {} on table {} {} typed by column {}, types {}",
+            self.name, self.schema, self.table, FkInfo::column_desc(&self.columns), self.type_column, types.join(", "))
     }
 }
 
@@ -70,6 +352,35 @@ pub struct FkIndex {
     pub fks_by_ref_table: HashMap<String, Vec<Rc<FkInfo>>>,
 }
 
+impl FkIndex {
+    /// The constraints of tables referencing themselves, eg: category.parent_id -> category.id
+    pub fn self_referencing(&self) -> Vec<Rc<FkInfo>> {
+        self.fks.iter().filter(|fk| fk.is_self_referencing()).cloned().collect()
+    }
+
+    /// The polymorphic relationships, made of the branch FKs sharing a relationship name
+    pub fn polymorphic(&self) -> Vec<Polymorphic> {
+        let mut res: Vec<Polymorphic> = Vec::new();
+        for fk in self.fks.iter() {
+            let Some(discriminator) = &fk.discriminator else {
+                continue;
+            };
+            match res.iter_mut().find(|p| p.name == discriminator.relationship) {
+                Some(poly) => poly.branches.push(fk.clone()),
+                None => res.push(Polymorphic {
+                    name: discriminator.relationship.clone(),
+                    schema: fk.schema.clone(),
+                    table: fk.table.clone(),
+                    type_column: discriminator.column.clone(),
+                    columns: fk.columns.clone(),
+                    branches: vec![fk.clone()],
+                }),
+            }
+        }
+        res
+    }
+}
+
 // Pre indexed list of FkInfo, by constraint name / table name / referenced table name
 impl From<Vec<FkInfo>> for FkIndex {
     fn from(value: Vec<FkInfo>) -> Self {
@@ -85,7 +396,7 @@ impl From<Vec<FkInfo>> for FkIndex {
 
 #[cfg(test)]
 mod test {
-    use super::{FkIndex, FkInfo};
+    use super::{FkAction, FkIndex, FkInfo, FkSource};
     use mysql::Value;
 
     #[test]
@@ -145,6 +456,9 @@ mod test {
         assert_eq!(fks.unwrap().len(), 1);
         assert_eq!(fks.unwrap()[0].name, "fk2");
 
+        assert!(index.self_referencing().is_empty());
+        let index = FkIndex::from(vec![FkInfo::new((Value::from("fk3"), Value::from("sch"), Value::from("cat"), Value::from("parent_id"), Value::from("cat"), Value::from("id")))]);
+        assert_eq!(index.self_referencing().len(), 1);
     }
 
     #[test]
@@ -153,12 +467,60 @@ mod test {
             name: String::from("NAME"),
             schema: String::from("SCHEMA"),
             table: String::from("TABLE"),
-            column: String::from("COLUMN"),
+            columns: vec![String::from("COLUMN")],
+            ref_schema: String::from("SCHEMA"),
             ref_table: String::from("REF_TABLE"),
-            ref_column: String::from("REF_COLUMN")
+            ref_columns: vec![String::from("REF_COLUMN")],
+            on_delete: FkAction::Cascade,
+            is_virtual: false,
+            source: FkSource::Ddl,
+            ..Default::default()
         };
         let res = format!("{}", fk);
         assert_eq!(res, "NAME in schema SCHEMA on table TABLE column COLUMN referencing table REF_TABLE column REF_COLUMN");
     }
 
+    #[test]
+    fn test_fmt_composite_virtual() {
+        let fk = FkInfo {
+            name: String::from("NAME"),
+            schema: String::from("SCHEMA"),
+            table: String::from("TABLE"),
+            columns: vec![String::from("A"), String::from("B")],
+            ref_schema: String::from("OTHER"),
+            ref_table: String::from("REF_TABLE"),
+            ref_columns: vec![String::from("RA"), String::from("RB")],
+            is_virtual: true,
+            ..Default::default()
+        };
+        let res = format!("{}", fk);
+        assert_eq!(res, "NAME in schema SCHEMA on table TABLE columns (A, B) referencing table OTHER.REF_TABLE columns (RA, RB) (virtual)");
+        assert!(fk.is_cross_schema());
+        assert!(!fk.is_self_referencing());
+
+        let fk = FkInfo { scope_columns: vec![String::from("TENANT")], ref_scope_columns: vec![String::from("TENANT")], ..fk };
+        assert!(fk.to_string().ends_with("columns (RA, RB) within column TENANT (virtual)"));
+    }
+
+    #[test]
+    fn test_fk_action() {
+        assert_eq!("set  null".parse::<FkAction>(), Ok(FkAction::SetNull));
+        assert_eq!("NO ACTION".parse::<FkAction>(), Ok(FkAction::NoAction));
+        assert_eq!("CASCADE".parse::<FkAction>(), Ok(FkAction::Cascade));
+        assert!("DROP".parse::<FkAction>().is_err());
+        assert_eq!(FkAction::SetDefault.to_string(), "SET DEFAULT");
+    }
+
+    #[test]
+    fn test_merge_composite() {
+        let row = |name: &str, col: &str, ref_col: &str| FkInfo::new(
+            (Value::from(name), Value::from("sch"), Value::from("tb1"), Value::from(col), Value::from("rt1"), Value::from(ref_col))
+        );
+        let res = FkInfo::merge_composite(vec![row("fk1", "a", "ra"), row("fk1", "b", "rb"), row("fk2", "c", "rc")]);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].columns, vec!["a", "b"]);
+        assert_eq!(res[0].ref_columns, vec!["ra", "rb"]);
+        assert_eq!(res[1].columns, vec!["c"]);
+    }
+
 }
@@ -2,15 +2,20 @@
 
 use std::ops::Add;
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::{fs, io};
 use std::io::{stdout, ErrorKind, BufWriter};
 
 use mysql::*;
 use mysql::prelude::*;
 
-use crate::fk::{FkInfo, FkIndex};
+use crate::config::Assertion;
+use crate::fk::{FkInfo, FkIndex, Polymorphic};
 use crate::datadumper;
 
+/// Maximum number of unreferenced rows of a table listed by check_unreferenced(), all are dumped
+pub const MAX_LISTED_KEYS: usize = 20;
+
 /// Configuration for function check()
 pub struct FkChecker {
     pub auto_delete: bool,
@@ -41,74 +46,396 @@ impl FkChecker {
 
     /// Return a list of all invalid foreign references.
     /// Deletes the invalid rows if auto_delete is true.
-    /// Param T should be the type of the foreign column.
+    /// Param T should be the type of the foreign column(s), eg: u32 or (u32, String) for a composite FK.
     pub fn check<T, C>(&self, fk_info: &FkInfo, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
-        where T: FromValue, C: Queryable
+        where T: FromRow, C: Queryable
+    {
+        self.check_partition(fk_info, None, fk_idx, conn)
+    }
+
+    /// Same as check(), on the rows of one partition of the table of the FK only.
+    /// The invalid rows are dumped to <fk name>_<partition>.csv.
+    pub fn check_partition<T, C>(&self, fk_info: &FkInfo, partition: Option<&str>, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
+        where T: FromRow, C: Queryable
+    {
+        let name = match partition {
+            Some(p) => format!("{}_{p}", fk_info.name),
+            None => fk_info.name.clone(),
+        };
+        self.check_query(fk_info, Self::check_query_sql(fk_info, partition), &fk_info.key_columns(), &name, fk_idx, conn)
+    }
+
+    /// Rows whose referenced row does not exist
+    fn check_query_sql(fk_info: &FkInfo, partition: Option<&str>) -> String {
+        let select = fk_info.key_columns().iter()
+            .map(|c| format!("a.{c}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let join_on = fk_info.columns.iter().zip(fk_info.ref_columns.iter())
+            .map(|(c, rc)| format!("a.{c}=b.{rc}"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let not_null = fk_info.columns.iter()
+            .map(|c| format!("a.{c} IS NOT NULL"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let partition_clause = partition.map(|p| format!(" PARTITION ({p})")).unwrap_or_default();
+        format!(
+            r"SELECT {}
+            FROM {}.{}{} a
+            LEFT JOIN {}.{} b ON {}
+            WHERE {}{} AND b.{} IS NULL;",
+            select, fk_info.schema, fk_info.table, partition_clause, fk_info.ref_schema, fk_info.ref_table, join_on,
+            not_null, Self::discriminator_clause(fk_info, "a"), fk_info.ref_columns[0])
+    }
+
+    /// Condition on the type column of a polymorphic branch, eg: " AND a.commentable_type='Post'"
+    fn discriminator_clause(fk_info: &FkInfo, table: &str) -> String {
+        match &fk_info.discriminator {
+            Some(d) => format!(" AND {table}.{}={}", d.column, d.literal()),
+            None => String::new(),
+        }
+    }
+
+    /// Return the references to a parent row of another scope (eg: another tenant), when the FK has scope columns.
+    /// The invalid rows are dumped to <fk name>_cross_scope.csv, and deleted if auto_delete is true.
+    pub fn check_scope<T, C>(&self, fk_info: &FkInfo, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
+        where T: FromRow, C: Queryable
+    {
+        if fk_info.scope_columns.is_empty() {
+            return Ok(Vec::new());
+        }
+        // The rows are identified by their reference and their scope
+        let columns: Vec<String> = fk_info.key_columns().into_iter().chain(fk_info.scope_columns.iter().cloned()).collect();
+        self.check_query(fk_info, Self::scope_query(fk_info), &columns, &format!("{}_cross_scope", fk_info.name), fk_idx, conn)
+    }
+
+    /// Rows whose referenced row exists, but with other values in the scope columns
+    fn scope_query(fk_info: &FkInfo) -> String {
+        let select = fk_info.key_columns().iter().chain(fk_info.scope_columns.iter())
+            .map(|c| format!("a.{c}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let join_on = fk_info.columns.iter().zip(fk_info.ref_columns.iter())
+            .map(|(c, rc)| format!("a.{c}=b.{rc}"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let same_scope = fk_info.scope_columns.iter().zip(fk_info.ref_scope_columns.iter())
+            .map(|(c, rc)| format!("a.{c}<=>b.{rc}"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        format!("SELECT {select} FROM {}.{} a JOIN {}.{} b ON {join_on} WHERE NOT ({same_scope}){};",
+            fk_info.schema, fk_info.table, fk_info.ref_schema, fk_info.ref_table, Self::discriminator_clause(fk_info, "a"))
+    }
+
+    /// Return the references to a soft-deleted parent row, when the referenced table has an alive predicate.
+    /// The invalid rows are dumped to <fk name>_soft_deleted.csv, and deleted if auto_delete is true.
+    pub fn check_alive<T, C>(&self, fk_info: &FkInfo, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
+        where T: FromRow, C: Queryable
+    {
+        let Some(query) = Self::alive_query(fk_info) else {
+            return Ok(Vec::new());
+        };
+        self.check_query(fk_info, query, &fk_info.key_columns(), &format!("{}_soft_deleted", fk_info.name), fk_idx, conn)
+    }
+
+    /// Rows whose referenced row exists, but is not alive. The predicate is evaluated in a derived table,
+    /// so that its columns are those of the referenced table.
+    fn alive_query(fk_info: &FkInfo) -> Option<String> {
+        let alive = fk_info.ref_alive.as_ref()?;
+        let select = fk_info.key_columns().iter()
+            .map(|c| format!("a.{c}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let join_on = fk_info.columns.iter().zip(fk_info.ref_columns.iter())
+            .map(|(c, rc)| format!("a.{c}=b.{rc}"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        Some(format!("SELECT {select} FROM {}.{} a JOIN (SELECT {}, ({alive}) AS is_alive FROM {}.{}) b ON {join_on} WHERE NOT b.is_alive{};",
+            fk_info.schema, fk_info.table, fk_info.ref_columns.join(", "), fk_info.ref_schema, fk_info.ref_table, Self::discriminator_clause(fk_info, "a")))
+    }
+
+    /// Return the references to a parent row which exists but is not valid at the date of the row, when the FK
+    /// has a validity period. The invalid rows are dumped to <fk name>_not_valid.csv, and deleted if auto_delete is true.
+    pub fn check_validity<T, C>(&self, fk_info: &FkInfo, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
+        where T: FromRow, C: Queryable
+    {
+        let (Some(query), Some(validity)) = (Self::validity_query(fk_info), &fk_info.validity) else {
+            return Ok(Vec::new());
+        };
+        // The rows are identified by their reference and their date
+        let columns: Vec<String> = fk_info.key_columns().into_iter().chain(std::iter::once(validity.date_column.clone())).collect();
+        self.check_query(fk_info, query, &columns, &format!("{}_not_valid", fk_info.name), fk_idx, conn)
+    }
+
+    /// Rows having referenced rows, none of them being valid at the date of the row.
+    /// The referenced columns need not be unique, eg: one row per version of a tariff.
+    fn validity_query(fk_info: &FkInfo) -> Option<String> {
+        let validity = fk_info.validity.as_ref()?;
+        let select = fk_info.key_columns().iter().chain(std::iter::once(&validity.date_column))
+            .map(|c| format!("a.{c}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let join_on = fk_info.columns.iter().zip(fk_info.ref_columns.iter())
+            .map(|(c, rc)| format!("b.{rc}=a.{c}"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let date = format!("a.{}", validity.date_column);
+        Some(format!("SELECT {select} FROM {}.{} a WHERE {date} IS NOT NULL{} \
+            AND EXISTS (SELECT 1 FROM {ref_table} b WHERE {join_on}) \
+            AND NOT EXISTS (SELECT 1 FROM {ref_table} b WHERE {join_on} \
+            AND (b.{from} IS NULL OR b.{from}<={date}) AND (b.{to} IS NULL OR {date}<b.{to}));",
+            fk_info.schema, fk_info.table, Self::discriminator_clause(fk_info, "a"),
+            ref_table = format!("{}.{}", fk_info.ref_schema, fk_info.ref_table), from = validity.valid_from, to = validity.valid_to))
+    }
+
+    /// Return the referenced rows having fewer or more referencing rows than the cardinality of the FK,
+    /// as the values of the referenced columns and the number of referencing rows.
+    /// The referenced rows are dumped to <fk name>_cardinality.csv, they are never deleted.
+    pub fn check_cardinality<C>(&self, fk_info: &FkInfo, conn: &mut C) -> Result<Vec<(Vec<Value>, u64)>>
+        where C: Queryable
+    {
+        let Some(query) = Self::cardinality_query(fk_info) else {
+            return Ok(Vec::new());
+        };
+        let n = fk_info.ref_columns.len();
+        let res: Vec<(Vec<Value>, u64)> = conn.query::<Row, String>(query)?.into_iter()
+            .map(|row| {
+                let mut values = row.unwrap();
+                let count = from_value(values.pop().unwrap_or(Value::NULL));
+                values.truncate(n);
+                (values, count)
+            })
+            .collect();
+        if self.dump_invalid_rows && !res.is_empty() {
+            let keys: Vec<Vec<Value>> = res.iter().map(|(key, _)| key.clone()).collect();
+            self.dump_table(&fk_info.ref_schema, &fk_info.ref_table, &fk_info.ref_columns, &format!("{}_cardinality", fk_info.name), &keys, conn)?;
+        }
+        Ok(res)
+    }
+
+    /// Referenced rows with their number of referencing rows, out of the bounds of the cardinality
+    fn cardinality_query(fk_info: &FkInfo) -> Option<String> {
+        let cardinality = fk_info.cardinality?;
+        let keys = fk_info.ref_columns.iter()
+            .map(|rc| format!("p.{rc}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let join_on = fk_info.columns.iter().zip(fk_info.ref_columns.iter())
+            .map(|(c, rc)| format!("c.{c}=p.{rc}"))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let mut having = format!("children<{}", cardinality.min);
+        if let Some(max) = cardinality.max {
+            having += &format!(" OR children>{max}");
+        }
+        Some(format!("SELECT {keys}, COUNT(c.{}) AS children FROM {}.{} p LEFT JOIN {}.{} c ON {join_on}{} GROUP BY {keys} HAVING {having};",
+            fk_info.columns[0], fk_info.ref_schema, fk_info.ref_table, fk_info.schema, fk_info.table, Self::discriminator_clause(fk_info, "c")))
+    }
+
+    /// Return the number of rows returned by the query of an assertion, which must return none.
+    /// The rows are dumped to <assertion name>.csv, and the fix statement of the assertion is run if auto_delete is true.
+    pub fn check_assertion<C>(&self, assertion: &Assertion, conn: &mut C) -> Result<usize>
+        where C: Queryable
+    {
+        let mut rows: Vec<Row> = conn.query(&assertion.query)?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        if self.dump_invalid_rows {
+            let mut out = self.named_dump_output(&assertion.name)?;
+            datadumper::dump_columns(out.as_mut(), rows[0].columns_ref())?;
+            for row in rows.iter_mut() {
+                datadumper::dump_row(out.as_mut(), row)?;
+            }
+        }
+        if self.auto_delete {
+            if let Some(fix) = &assertion.fix {
+                println!("Running the fix of assertion {}: {fix}", assertion.name);
+                conn.query_drop(fix)?;
+            }
+        }
+        Ok(rows.len())
+    }
+
+    /// Return the number of rows of a parent table referenced by none of `fks`, its incoming constraints,
+    /// and the values of the referenced columns of the first MAX_LISTED_KEYS of them.
+    /// The rows are dumped to <table>_unreferenced.csv, they are never deleted.
+    pub fn check_unreferenced<C>(&self, fks: &[Rc<FkInfo>], conn: &mut C) -> Result<(usize, Vec<Vec<Value>>)>
+        where C: Queryable
     {
-        let query = format!(
-            r"SELECT a.{}
-            FROM {}.{} a
-            LEFT JOIN {}.{} b ON a.{}=b.{}
-            WHERE a.{} IS NOT NULL AND b.{} IS NULL;",
-            fk_info.column, fk_info.schema, fk_info.table, fk_info.schema, fk_info.ref_table, fk_info.column, fk_info.ref_column, fk_info.column, fk_info.ref_column);
+        let Some(fk) = fks.first() else {
+            return Ok((0, Vec::new()));
+        };
+        let condition = Self::unreferenced_condition(fks);
+        let count: Option<usize> = conn.query_first(format!("SELECT COUNT(*) FROM {}.{} p WHERE {condition};", fk.ref_schema, fk.ref_table))?;
+        let count = count.unwrap_or_default();
+        if count == 0 {
+            return Ok((0, Vec::new()));
+        }
+        let keys = fk.ref_columns.iter().map(|rc| format!("p.{rc}")).collect::<Vec<String>>().join(", ");
+        let query = format!("SELECT {keys} FROM {}.{} p WHERE {condition} ORDER BY {keys} LIMIT {MAX_LISTED_KEYS};", fk.ref_schema, fk.ref_table);
+        let listed: Vec<Vec<Value>> = conn.query::<Row, String>(query)?.into_iter().map(Row::unwrap).collect();
+        if self.dump_invalid_rows {
+            let query = format!("SELECT p.* FROM {}.{} p WHERE {condition};", fk.ref_schema, fk.ref_table);
+            self.dump_query(query, &format!("{}_unreferenced", fk.ref_table), &[Vec::new()], conn)?;
+        }
+        Ok((count, listed))
+    }
+
+    /// Condition on the rows of the parent table `p`: no row of a child table references it
+    fn unreferenced_condition(fks: &[Rc<FkInfo>]) -> String {
+        fks.iter()
+            .map(|fk| {
+                let join_on = fk.columns.iter().zip(fk.ref_columns.iter())
+                    .map(|(c, rc)| format!("c.{c}=p.{rc}"))
+                    .collect::<Vec<String>>()
+                    .join(" AND ");
+                format!("NOT EXISTS (SELECT 1 FROM {}.{} c WHERE {join_on}{})", fk.schema, fk.table, Self::discriminator_clause(fk, "c"))
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ")
+    }
+
+    /// Return the rows of a polymorphic relationship whose type is not one of its branches.
+    /// The invalid rows are dumped to <relationship name>_unknown_type.csv, and deleted if auto_delete is true.
+    pub fn check_types<T, C>(&self, poly: &Polymorphic, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
+        where T: FromRow, C: Queryable
+    {
+        let columns: Vec<String> = std::iter::once(poly.type_column.clone()).chain(poly.columns.iter().cloned()).collect();
+        // Any branch gives the table of the rows
+        self.check_query(&poly.branches[0], Self::types_query(poly), &columns, &format!("{}_unknown_type", poly.name), fk_idx, conn)
+    }
 
-        let ids = conn.query::<Value, String>(query)?;
+    /// Rows having a type but none of the known ones
+    fn types_query(poly: &Polymorphic) -> String {
+        let select = std::iter::once(&poly.type_column).chain(poly.columns.iter())
+            .map(|c| format!("a.{c}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let types = poly.types().iter()
+            .map(|d| d.literal())
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("SELECT {select} FROM {}.{} a WHERE a.{type_column} IS NOT NULL AND a.{type_column} NOT IN ({types});",
+            poly.schema, poly.table, type_column = poly.type_column)
+    }
+
+    /// Run a query returning the `columns` of invalid rows, dump them to the output named `name`
+    /// and delete them if auto_delete is true
+    fn check_query<T, C>(&self, fk_info: &FkInfo, query: String, columns: &[String], name: &str, fk_idx: &FkIndex, conn: &mut C) -> Result<Vec<T>>
+        where T: FromRow, C: Queryable
+    {
+        let ids = conn.query::<Row, String>(query)?;
 
         if self.dump_invalid_rows && !ids.is_empty() {
-            self.dump_rows(fk_info, &ids, fk_idx, conn)?;
+            let values: Vec<Vec<Value>> = ids.iter().map(|id| id.clone().unwrap()).collect();
+            self.dump_rows(fk_info, columns, name, &values, fk_idx, conn)?;
         }
 
         if self.auto_delete && !ids.is_empty() {
-            self.delete_all(fk_info, &ids, conn)?;
+            self.delete_all(fk_info, columns, &ids, conn)?;
         }
 
         let res: Vec<T> = ids.into_iter() // into_iter consumes the collection
-            .map(|id| T::from_value(id))
+            .map(|id| T::from_row(id))
             .collect();
 
-        return Ok(res)
+        Ok(res)
+    }
+
+    /// WHERE clause matching the columns, eg: the FK columns with the values of an invalid reference
+    fn where_columns(table: &str, columns: &[String]) -> String {
+        columns.iter()
+            .map(|c| format!("{table}.{c}<=>?"))
+            .collect::<Vec<String>>()
+            .join(" AND ")
     }
 
-    /// Deletes all rows having an invalid foreign reference
+    /// Deletes all rows having an invalid foreign reference, `ids` being the values of their `columns`
     /// Performs one batch query
-    fn delete_all<C>(&self, fk_info: &FkInfo, ids: &Vec<Value>, conn: &mut C)-> Result<()>
+    fn delete_all<C>(&self, fk_info: &FkInfo, columns: &[String], ids: &[Row], conn: &mut C)-> Result<()>
+        where C: Queryable
+    {
+        let query = format!("DELETE FROM {}.{} WHERE {}", fk_info.schema, fk_info.table, Self::where_columns(&fk_info.table, columns));
+        conn.exec_batch(query, ids.iter().map(|x| x.clone().unwrap()))
+    }
+
+    /// Output of the dump of the invalid rows of an FK: <dump_location>/<fk name>.csv, or stdout
+    pub fn dump_output(&self, fk_info: &FkInfo) -> io::Result<Box<dyn io::Write>> {
+        self.named_dump_output(&fk_info.name)
+    }
+
+    /// Output of a dump: <dump_location>/<name>.csv, or stdout
+    fn named_dump_output(&self, name: &str) -> io::Result<Box<dyn io::Write>> {
+        Ok(match &self.dump_location {
+            None => Box::new(stdout()),
+            Some(dump_loc) => {
+                let fname = name.to_string().add(".csv");
+                let path = dump_loc.clone().join(fname);
+                Box::new(BufWriter::new(fs::File::create(path)?))
+            }
+        })
+    }
+
+    /// Dumps all rows whose FK columns (and type column of a polymorphic branch) have one of the `values`, to the output named `name`
+    pub fn dump_referencing<C>(&self, fk_info: &FkInfo, name: &str, values: &[Vec<Value>], fk_idx: &FkIndex, conn: &mut C) -> Result<()>
+        where C: Queryable
+    {
+        self.dump_rows(fk_info, &fk_info.key_columns(), name, values, fk_idx, conn)
+    }
+
+    /// Dumps the rows of a self-referencing table whose referenced columns have one of the `values`
+    pub fn dump_keys<C>(&self, fk_info: &FkInfo, name: &str, values: &[Vec<Value>], fk_idx: &FkIndex, conn: &mut C) -> Result<()>
         where C: Queryable
     {
-        let query = format!("DELETE FROM {}.{} WHERE {}=?", fk_info.schema, fk_info.table, fk_info.column);
-        conn.exec_batch(query, ids.iter().map(|x| (x,)))
+        self.dump_rows(fk_info, &fk_info.ref_columns, name, values, fk_idx, conn)
     }
 
-    /// Dumps all rows
-    fn dump_rows<C>(&self, fk_info: &FkInfo, ids: &Vec<Value>, fk_idx: &FkIndex, conn: &mut C) -> Result<()>
+    /// Dumps all rows whose `columns` have one of the `values`, with the rows they reference through the other FKs
+    pub fn dump_rows<C>(&self, fk_info: &FkInfo, columns: &[String], name: &str, values: &[Vec<Value>], fk_idx: &FkIndex, conn: &mut C) -> Result<()>
         where C: Queryable
     {
         let mut query = format!("SELECT * FROM {}.{}", fk_info.schema, fk_info.table);
         if let Some(fks) = fk_idx.fks_by_table.get(&fk_info.table) {
             if fks.len() > 1 {
                 if let Some(clause) = fks.iter()
-                    .filter(|f| f.name != fk_info.name)
-                    .map(|f| format!(" LEFT JOIN {}.{} ON {}.{}={}.{}", f.schema, f.ref_table, f.table, f.column, f.ref_table, f.ref_column))
+                    .filter(|f| f.name != fk_info.name && !f.is_embedded())
+                    .map(|f| {
+                        let join_on = f.columns.iter().zip(f.ref_columns.iter())
+                            .map(|(c, rc)| format!("{}.{c}={}.{rc}", f.table, f.ref_table))
+                            .collect::<Vec<String>>()
+                            .join(" AND ");
+                        format!(" LEFT JOIN {}.{} ON {}{}", f.ref_schema, f.ref_table, join_on, Self::discriminator_clause(f, &f.table))
+                    })
                     .reduce(|acc, e| acc + &e) {
                         query = query.add(&clause);
                     }
             }
         }
-        query = query + &format!(" WHERE {}=?;", fk_info.column);
+        query = query + &format!(" WHERE {};", Self::where_columns(&fk_info.table, columns));
+        self.dump_query(query, name, values, conn)
+    }
+
+    /// Dumps the rows of a table whose `columns` have one of the `values`, eg: referenced rows
+    pub fn dump_table<C>(&self, schema: &str, table: &str, columns: &[String], name: &str, values: &[Vec<Value>], conn: &mut C) -> Result<()>
+        where C: Queryable
+    {
+        let query = format!("SELECT * FROM {schema}.{table} WHERE {};", Self::where_columns(table, columns));
+        self.dump_query(query, name, values, conn)
+    }
+
+    /// Dumps the rows of a query having one parameter per column, run once for each of the `values`
+    fn dump_query<C>(&self, query: String, name: &str, values: &[Vec<Value>], conn: &mut C) -> Result<()>
+        where C: Queryable
+    {
         let preped = conn.prep(query)?;
 
         let mut col_disp = true;
-        // Output to dump_location, or to stdout
-        let mut out: Box<dyn io::Write> = match &self.dump_location {
-            None => Box::new(stdout()),
-            Some(dump_loc) => {
-                let fname = fk_info.name.clone().add(".csv");
-                let path = dump_loc.clone().join(fname);
-                Box::new(BufWriter::new(fs::File::create(path)?))
-            }
-        };
+        let mut out = self.named_dump_output(name)?;
 
-        for id in ids {
-            let it = conn.exec_iter(&preped, (id,))?;
+        for id in values {
+            let it = conn.exec_iter(&preped, id.clone())?;
 
             if col_disp {
                 datadumper::dump_columns(out.as_mut(), it.columns().as_ref())?;
@@ -128,7 +455,11 @@ impl FkChecker {
 mod test {
     use std::{fs::{File, self}, path::PathBuf};
 
+    use mysql::Value;
+
     use super::FkChecker;
+    use crate::config::Config;
+    use crate::fk::{Cardinality, FkIndex, FkInfo, Validity};
 
     #[test]
     fn should_handle_empty_dump_location() {
@@ -187,4 +518,95 @@ mod test {
         assert!(path.is_dir());
         fs::remove_dir(path).expect("dump_dir should be removable in test folder");
     }
+
+    #[test]
+    fn scope_query() {
+        let fk = FkInfo {
+            scope_columns: vec![String::from("tenant_id")],
+            ref_scope_columns: vec![String::from("tenant")],
+            ..FkInfo::new((Value::from("fk1"), Value::from("sch"), Value::from("order"), Value::from("customer_id"), Value::from("customer"), Value::from("id")))
+        };
+        assert_eq!(FkChecker::scope_query(&fk), "SELECT a.customer_id, a.tenant_id FROM sch.order a JOIN sch.customer b ON a.customer_id=b.id WHERE NOT (a.tenant_id<=>b.tenant);");
+    }
+
+    #[test]
+    fn where_columns() {
+        // A NULL scope column must match the NULL of the invalid row
+        let columns = vec![String::from("customer_id"), String::from("tenant_id")];
+        assert_eq!(FkChecker::where_columns("order", &columns), "order.customer_id<=>? AND order.tenant_id<=>?");
+    }
+
+    #[test]
+    fn alive_query() {
+        let fk = FkInfo::new((Value::from("fk1"), Value::from("sch"), Value::from("order"), Value::from("customer_id"), Value::from("customer"), Value::from("id")));
+        assert!(FkChecker::alive_query(&fk).is_none());
+        let fk = FkInfo { ref_alive: Some(String::from("deleted_at IS NULL")), ..fk };
+        assert_eq!(FkChecker::alive_query(&fk).expect("alive query"), "SELECT a.customer_id FROM sch.order a \
+            JOIN (SELECT id, (deleted_at IS NULL) AS is_alive FROM sch.customer) b ON a.customer_id=b.id WHERE NOT b.is_alive;");
+    }
+
+    #[test]
+    fn validity_query() {
+        let fk = FkInfo::new((Value::from("fk1"), Value::from("sch"), Value::from("price_line"), Value::from("tariff_id"), Value::from("tariff"), Value::from("id")));
+        assert!(FkChecker::validity_query(&fk).is_none());
+        let validity = Validity { date_column: String::from("effective_date"), valid_from: String::from("valid_from"), valid_to: String::from("valid_to") };
+        let fk = FkInfo { validity: Some(validity), ..fk };
+        assert_eq!(FkChecker::validity_query(&fk).expect("validity query"), "SELECT a.tariff_id, a.effective_date FROM sch.price_line a \
+            WHERE a.effective_date IS NOT NULL AND EXISTS (SELECT 1 FROM sch.tariff b WHERE b.id=a.tariff_id) \
+            AND NOT EXISTS (SELECT 1 FROM sch.tariff b WHERE b.id=a.tariff_id \
+            AND (b.valid_from IS NULL OR b.valid_from<=a.effective_date) AND (b.valid_to IS NULL OR a.effective_date<b.valid_to));");
+    }
+
+    #[test]
+    fn cardinality_query() {
+        let fk = FkInfo::new((Value::from("fk1"), Value::from("sch"), Value::from("line"), Value::from("order_id"), Value::from("order"), Value::from("id")));
+        assert!(FkChecker::cardinality_query(&fk).is_none());
+        let fk = FkInfo { cardinality: Some(Cardinality { min: 1, max: Some(50) }), ..fk };
+        assert_eq!(FkChecker::cardinality_query(&fk).expect("cardinality query"), "SELECT p.id, COUNT(c.order_id) AS children \
+            FROM sch.order p LEFT JOIN sch.line c ON c.order_id=p.id GROUP BY p.id HAVING children<1 OR children>50;");
+    }
+
+    #[test]
+    fn unreferenced_condition() {
+        let config = Config::parse(r#"
+            [[virtual_fk]]
+            schema = "sch"
+            table = "order"
+            columns = ["customer_id"]
+            ref_table = "customer"
+            ref_columns = ["id"]
+
+            [[polymorphic]]
+            schema = "sch"
+            table = "address"
+            type_column = "owner_type"
+            columns = ["owner_id"]
+            types = { Customer = { ref_table = "customer" } }
+        "#).unwrap();
+        let fk_idx = FkIndex::from(config.virtual_fk_infos(None).unwrap());
+        assert_eq!(FkChecker::unreferenced_condition(&fk_idx.fks_by_ref_table["customer"]),
+            "NOT EXISTS (SELECT 1 FROM sch.order c WHERE c.customer_id=p.id) \
+            AND NOT EXISTS (SELECT 1 FROM sch.address c WHERE c.owner_id=p.id AND c.owner_type='Customer')");
+    }
+
+    #[test]
+    fn polymorphic_queries() {
+        let config = Config::parse(r#"
+            [[polymorphic]]
+            schema = "sch"
+            table = "comment"
+            type_column = "commentable_type"
+            columns = ["commentable_id"]
+            types = { Post = { ref_table = "post" }, "Video's" = { ref_table = "video" } }
+        "#).unwrap();
+        let fk_idx = FkIndex::from(config.virtual_fk_infos(None).unwrap());
+        let fk = &fk_idx.fks_by_name["poly_comment_commentable_id_Post"];
+        assert_eq!(FkChecker::check_query_sql(fk, None), "SELECT a.commentable_id, a.commentable_type
+            FROM sch.comment a
+            LEFT JOIN sch.post b ON a.commentable_id=b.id
+            WHERE a.commentable_id IS NOT NULL AND a.commentable_type='Post' AND b.id IS NULL;");
+        let poly = fk_idx.polymorphic();
+        assert_eq!(FkChecker::types_query(&poly[0]), "SELECT a.commentable_type, a.commentable_id FROM sch.comment a \
+            WHERE a.commentable_type IS NOT NULL AND a.commentable_type NOT IN ('Post', 'Video\\'s');");
+    }
 }
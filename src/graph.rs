@@ -0,0 +1,190 @@
+//! Foreign Key dependency graph: tables are nodes, FK constraints are edges from the referenced table to the table
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::fk::FkIndex;
+
+/// Direction to follow the FK constraints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From a table to the tables it references
+    Parents,
+    /// From a table to the tables referencing it
+    Children,
+}
+
+/// Graph of the tables of an FkIndex
+pub struct FkGraph<'a> {
+    fk_idx: &'a FkIndex,
+    tables: Vec<String>,
+}
+
+impl<'a> FkGraph<'a> {
+    pub fn new(fk_idx: &'a FkIndex) -> Self {
+        let tables: BTreeSet<String> = fk_idx.fks.iter()
+            .flat_map(|fk| [fk.table.clone(), fk.ref_table.clone()])
+            .collect();
+        FkGraph { fk_idx, tables: tables.into_iter().collect() }
+    }
+
+    /// All the tables having or referenced by an FK, sorted by name
+    pub fn tables(&self) -> &[String] {
+        &self.tables
+    }
+
+    /// Tables directly linked to `table`, sorted by name, without duplicates
+    pub fn neighbours(&self, table: &str, direction: Direction) -> Vec<&'a str> {
+        let res: BTreeSet<&'a str> = match direction {
+            Direction::Parents => self.fk_idx.fks_by_table.get(table)
+                .map(|fks| fks.iter().map(|fk| fk.ref_table.as_str()).collect())
+                .unwrap_or_default(),
+            Direction::Children => self.fk_idx.fks_by_ref_table.get(table)
+                .map(|fks| fks.iter().map(|fk| fk.table.as_str()).collect())
+                .unwrap_or_default(),
+        };
+        res.into_iter().collect()
+    }
+
+    /// Strongly connected components (Tarjan), parents before children.
+    /// A component with more than one table, or a self-referencing table, is an FK cycle.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<String>> {
+        let mut tarjan = Tarjan { graph: self, index: 0, indexes: HashMap::new(), lowlinks: HashMap::new(), stack: Vec::new(), on_stack: HashSet::new(), components: Vec::new() };
+        for table in self.tables.iter() {
+            if !tarjan.indexes.contains_key(table.as_str()) {
+                tarjan.visit(table);
+            }
+        }
+        // Tarjan outputs the components children first
+        let mut res = tarjan.components;
+        res.reverse();
+        res.iter_mut().for_each(|c| c.sort());
+        res
+    }
+
+    /// FK cycles: components of more than one table, and self-referencing tables
+    pub fn cycles(&self) -> Vec<Vec<String>> {
+        self.strongly_connected_components().into_iter()
+            .filter(|c| c.len() > 1 || self.neighbours(&c[0], Direction::Children).contains(&c[0].as_str()))
+            .collect()
+    }
+
+    /// Tables ordered parents before children.
+    /// Self references are ignored, and the tables of a cycle are next to each other (sorted by name).
+    pub fn topological_order(&self) -> Vec<String> {
+        self.strongly_connected_components().into_iter().flatten().collect()
+    }
+
+    /// Tables reachable from `table` following the FKs in `direction`, `table` first then by distance
+    pub fn reachable_from(&self, table: &str, direction: Direction) -> Vec<String> {
+        let mut res = vec![table.to_string()];
+        let mut seen: HashSet<&str> = HashSet::from([table]);
+        let mut queue: VecDeque<&str> = VecDeque::from([table]);
+        while let Some(current) = queue.pop_front() {
+            for next in self.neighbours(current, direction) {
+                if seen.insert(next) {
+                    res.push(next.to_string());
+                    queue.push_back(next);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// State of Tarjan's strongly connected components algorithm
+struct Tarjan<'g, 'a> {
+    graph: &'g FkGraph<'a>,
+    index: usize,
+    indexes: HashMap<&'g str, usize>,
+    lowlinks: HashMap<&'g str, usize>,
+    stack: Vec<&'g str>,
+    on_stack: HashSet<&'g str>,
+    components: Vec<Vec<String>>,
+}
+
+impl<'g, 'a> Tarjan<'g, 'a> {
+    fn visit(&mut self, table: &'g str) {
+        self.indexes.insert(table, self.index);
+        self.lowlinks.insert(table, self.index);
+        self.index += 1;
+        self.stack.push(table);
+        self.on_stack.insert(table);
+
+        for child in self.graph.neighbours(table, Direction::Children) {
+            if !self.indexes.contains_key(child) {
+                self.visit(child);
+                let low = self.lowlinks[table].min(self.lowlinks[child]);
+                self.lowlinks.insert(table, low);
+            } else if self.on_stack.contains(child) {
+                let low = self.lowlinks[table].min(self.indexes[child]);
+                self.lowlinks.insert(table, low);
+            }
+        }
+
+        if self.lowlinks[table] == self.indexes[table] {
+            let mut component = Vec::new();
+            while let Some(t) = self.stack.pop() {
+                self.on_stack.remove(t);
+                component.push(t.to_string());
+                if t == table {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, FkGraph};
+    use crate::fk::{FkIndex, FkInfo};
+    use mysql::Value;
+
+    fn fk(name: &str, table: &str, ref_table: &str) -> FkInfo {
+        FkInfo::new((Value::from(name), Value::from("sch"), Value::from(table), Value::from("col"), Value::from(ref_table), Value::from("id")))
+    }
+
+    /// line -> order -> customer, order -> shop, employee -> employee, a -> b -> a
+    fn index() -> FkIndex {
+        FkIndex::from(vec![
+            fk("fk1", "line", "order"),
+            fk("fk2", "order", "customer"),
+            fk("fk3", "order", "shop"),
+            fk("fk4", "employee", "employee"),
+            fk("fk5", "a", "b"),
+            fk("fk6", "b", "a"),
+        ])
+    }
+
+    #[test]
+    fn test_topological_order() {
+        let idx = index();
+        let graph = FkGraph::new(&idx);
+        let order = graph.topological_order();
+        assert_eq!(order.len(), 7);
+        let pos = |t: &str| order.iter().position(|o| o == t).unwrap();
+        assert!(pos("customer") < pos("order"));
+        assert!(pos("shop") < pos("order"));
+        assert!(pos("order") < pos("line"));
+        assert_eq!((pos("a") as i64 - pos("b") as i64).abs(), 1);
+    }
+
+    #[test]
+    fn test_cycles() {
+        let idx = index();
+        let graph = FkGraph::new(&idx);
+        assert_eq!(graph.cycles(), vec![vec!["employee"], vec!["a", "b"]]);
+        assert_eq!(graph.strongly_connected_components().len(), 6);
+    }
+
+    #[test]
+    fn test_reachable_from() {
+        let idx = index();
+        let graph = FkGraph::new(&idx);
+        assert_eq!(graph.reachable_from("customer", Direction::Children), vec!["customer", "order", "line"]);
+        assert_eq!(graph.reachable_from("line", Direction::Parents), vec!["line", "order", "customer", "shop"]);
+        assert_eq!(graph.reachable_from("employee", Direction::Children), vec!["employee"]);
+    }
+}
@@ -0,0 +1,377 @@
+//! Self-referencing FK constraints (hierarchies like category.parent_id -> category.id).
+//! Deleting an orphaned node orphans its children, so the orphans are resolved as whole subtrees.
+//! Rows referencing each other in a loop (A -> B -> A) break recursive queries, they are reported as cycles.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::rc::Rc;
+
+use mysql::prelude::Queryable;
+use mysql::{from_value, Conn, Result, Row, TxOpts, Value};
+
+use crate::fk::FkInfo;
+use crate::utils::key_str;
+
+/// Maximum number of rows in the IN () list of a query
+const BATCH_SIZE: usize = 1000;
+
+/// A row of an orphaned subtree
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrphanNode {
+    /// Values of the referenced columns, identifying the row
+    pub key: Vec<Value>,
+    /// 0 for the root
+    pub depth: u32,
+}
+
+/// A row referencing a missing row, with all its descendants
+#[derive(Debug)]
+pub struct OrphanSubtree {
+    pub fk: Rc<FkInfo>,
+    /// Values of the FK columns of the root
+    pub missing: Vec<Value>,
+    /// The root first, then by depth
+    pub nodes: Vec<OrphanNode>,
+}
+
+impl OrphanSubtree {
+    /// Depth of the deepest node, 0 if the root has no children
+    pub fn depth(&self) -> u32 {
+        self.nodes.iter().map(|n| n.depth).max().unwrap_or_default()
+    }
+}
+
+impl Display for OrphanSubtree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Orphaned subtree of {} rows and depth {} in table {}, root {} references missing {}",
+            self.nodes.len(), self.depth(), self.fk.table,
+            values_desc(&self.fk.ref_columns, &self.nodes[0].key), values_desc(&self.fk.columns, &self.missing))
+    }
+}
+
+/// eg: id=12, shop='a'
+pub fn values_desc(columns: &[String], values: &[Value]) -> String {
+    columns.iter().zip(values.iter())
+        .map(|(c, v)| format!("{c}={}", v.as_sql(false)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Aliases of a list of columns, eg: k0, k1
+fn aliases(prefix: &str, count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("{prefix}{i}")).collect()
+}
+
+/// `a.c AS k0, a.d AS k1`
+fn select_as(table: &str, columns: &[String], prefix: &str) -> String {
+    columns.iter().zip(aliases(prefix, columns.len()))
+        .map(|(c, alias)| format!("{table}.{c} AS {alias}"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Rows referencing a missing row: their key (k), their invalid reference (m)
+fn roots_query(fk: &FkInfo) -> String {
+    let join_on = fk.columns.iter().zip(fk.ref_columns.iter())
+        .map(|(c, rc)| format!("a.{c}=b.{rc}"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    let not_null = fk.columns.iter()
+        .map(|c| format!("a.{c} IS NOT NULL"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    format!("SELECT {}, {} FROM {}.{} a LEFT JOIN {}.{} b ON {join_on} WHERE {not_null} AND b.{} IS NULL",
+        select_as("a", &fk.ref_columns, "k"), select_as("a", &fk.columns, "m"),
+        fk.schema, fk.table, fk.schema, fk.table, fk.ref_columns[0])
+}
+
+/// The orphaned subtrees in one query (MySQL 8): key (k), key of the root (r), invalid reference of the root (m), depth
+fn recursive_query(fk: &FkInfo) -> String {
+    let n = fk.columns.len();
+    let roots = format!("SELECT {}, {}, {}, 0 AS depth FROM ({}) o",
+        aliases("o.k", n).join(", "), select_as("o", &aliases("k", n), "r"), select_as("o", &aliases("m", n), "m"), roots_query(fk));
+    let join_on = fk.columns.iter().zip(aliases("s.k", n))
+        .map(|(c, k)| format!("a.{c}={k}"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    let children = format!("SELECT {}, {}, {}, s.depth + 1 FROM {}.{} a JOIN subtree s ON {join_on}",
+        fk.ref_columns.iter().map(|rc| format!("a.{rc}")).collect::<Vec<String>>().join(", "),
+        aliases("s.r", n).join(", "), aliases("s.m", n).join(", "), fk.schema, fk.table);
+    format!("WITH RECURSIVE subtree ({}, {}, {}, depth) AS ({roots} UNION ALL {children}) SELECT * FROM subtree ORDER BY {}, depth",
+        aliases("k", n).join(", "), aliases("r", n).join(", "), aliases("m", n).join(", "), aliases("r", n).join(", "))
+}
+
+/// key, key of the root, invalid reference of the root, depth
+type NodeRow = (Vec<Value>, Vec<Value>, Vec<Value>, u32);
+
+/// Group rows by root, rows of a root are ordered by depth
+fn group(rows: Vec<NodeRow>, fk: &Rc<FkInfo>) -> Vec<OrphanSubtree> {
+    let mut res: Vec<OrphanSubtree> = Vec::new();
+    let mut by_root: HashMap<Vec<String>, usize> = HashMap::new();
+    for (key, root, missing, depth) in rows {
+        let idx = *by_root.entry(key_str(&root)).or_insert_with(|| {
+            res.push(OrphanSubtree { fk: fk.clone(), missing, nodes: Vec::new() });
+            res.len() - 1
+        });
+        res[idx].nodes.push(OrphanNode { key, depth });
+    }
+    for subtree in res.iter_mut() {
+        subtree.nodes.sort_by_key(|n| n.depth);
+    }
+    res
+}
+
+/// key, key of the root, invalid reference of the root
+type LevelNode = (Vec<Value>, Vec<Value>, Vec<Value>);
+
+/// The nodes of the next level: the `children` (key, reference) of the nodes of `level`, with the root of their parent
+fn children_of(level: &[LevelNode], children: Vec<(Vec<Value>, Vec<Value>)>) -> Vec<LevelNode> {
+    let parents: HashMap<Vec<String>, (&Vec<Value>, &Vec<Value>)> = level.iter()
+        .map(|(key, root, missing)| (key_str(key), (root, missing)))
+        .collect();
+    children.into_iter()
+        .filter_map(|(key, parent)| parents.get(&key_str(&parent)).map(|(root, missing)| (key, (*root).clone(), (*missing).clone())))
+        .collect()
+}
+
+/// Split a row in lists of `n` values
+fn split_row(row: Row, n: usize) -> Vec<Vec<Value>> {
+    row.unwrap().chunks(n).map(|c| c.to_vec()).collect()
+}
+
+/// Find the orphaned subtrees of a self-referencing FK, with a recursive CTE if the server supports it (MySQL 8),
+/// else one query per level of the hierarchy (MySQL 5.7)
+pub fn orphan_subtrees<C>(conn: &mut C, fk: &Rc<FkInfo>, recursive_cte: bool) -> Result<Vec<OrphanSubtree>>
+    where C: Queryable
+{
+    let n = fk.columns.len();
+    let mut rows: Vec<NodeRow> = Vec::new();
+    if recursive_cte {
+        for row in conn.query::<Row, String>(recursive_query(fk))? {
+            let mut values = row.unwrap();
+            let depth = from_value::<u32>(values.pop().unwrap_or(Value::NULL));
+            let parts: Vec<Vec<Value>> = values.chunks(n).map(|c| c.to_vec()).collect();
+            let [key, root, missing] = <[Vec<Value>; 3]>::try_from(parts).expect("3 groups of columns");
+            rows.push((key, root, missing, depth));
+        }
+        return Ok(group(rows, fk));
+    }
+
+    // Level by level: the children of the nodes of the previous level
+    let mut level: Vec<LevelNode> = Vec::new();
+    for row in conn.query::<Row, String>(roots_query(fk))? {
+        let [key, missing] = <[Vec<Value>; 2]>::try_from(split_row(row, n)).expect("2 groups of columns");
+        level.push((key.clone(), key, missing));
+    }
+    let mut seen: HashSet<Vec<String>> = HashSet::new();
+    let mut depth = 0;
+    while !level.is_empty() {
+        let mut children = Vec::new();
+        for batch in level.chunks(BATCH_SIZE) {
+            let keys: Vec<String> = batch.iter()
+                .map(|(key, _, _)| format!("({})", key.iter().map(|v| v.as_sql(false)).collect::<Vec<String>>().join(", ")))
+                .collect();
+            // Text protocol like the roots, so that the values of all the levels have the same representation
+            let query = format!("SELECT {}, {} FROM {}.{} a WHERE ({}) IN ({})",
+                select_as("a", &fk.ref_columns, "k"), select_as("a", &fk.columns, "p"), fk.schema, fk.table,
+                fk.columns.join(", "), keys.join(", "));
+            for row in conn.query::<Row, String>(query)? {
+                let [key, parent] = <[Vec<Value>; 2]>::try_from(split_row(row, n)).expect("2 groups of columns");
+                children.push((key, parent));
+            }
+        }
+        let mut next = children_of(&level, children);
+        for (key, root, missing) in level {
+            // A node seen twice would loop forever
+            if seen.insert(key_str(&key)) {
+                rows.push((key, root, missing, depth));
+            }
+        }
+        next.retain(|(key, _, _)| !seen.contains(&key_str(key)));
+        level = next;
+        depth += 1;
+    }
+    Ok(group(rows, fk))
+}
+
+/// Rows referencing each other in a loop
+#[derive(Debug)]
+pub struct Cycle {
+    pub fk: Rc<FkInfo>,
+    /// Keys of the rows, each row references the next one and the last row references the first one
+    pub path: Vec<Vec<Value>>,
+}
+
+impl Cycle {
+    /// The row whose reference closes the cycle
+    pub fn closing_row(&self) -> &[Value] {
+        self.path.last().expect("row of the cycle")
+    }
+}
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path: Vec<String> = self.path.iter().chain(self.path.first())
+            .map(|key| values_desc(&self.fk.ref_columns, key))
+            .collect();
+        write!(f, "Reference cycle of {} rows in table {} {}: {}", self.path.len(), self.fk.table, FkInfo::column_desc(&self.fk.columns), path.join(" -> "))
+    }
+}
+
+/// Find the cycles among the (key, reference) pairs of the rows, `edges` being in table order.
+/// Each row references at most one row, so a walk following the references ends in a row without reference or in a cycle.
+fn find_cycles(edges: Vec<(Vec<Value>, Vec<Value>)>, fk: &Rc<FkInfo>) -> Vec<Cycle> {
+    let index: HashMap<Vec<String>, usize> = edges.iter().enumerate().map(|(i, (key, _))| (key_str(key), i)).collect();
+    let next: Vec<Option<usize>> = edges.iter().map(|(_, reference)| index.get(&key_str(reference)).copied()).collect();
+    // 0: not visited, 1: in the current walk, 2: done
+    let mut state = vec![0u8; edges.len()];
+    let mut cycles: Vec<Vec<usize>> = Vec::new();
+    for start in 0..edges.len() {
+        let mut walk = Vec::new();
+        let mut current = Some(start);
+        while let Some(i) = current.filter(|i| state[*i] == 0) {
+            state[i] = 1;
+            walk.push(i);
+            current = next[i];
+        }
+        if let Some(i) = current.filter(|i| state[*i] == 1) {
+            let mut cycle = walk[walk.iter().position(|w| *w == i).expect("row of the walk")..].to_vec();
+            // Starts with the first row of the table, for a stable output
+            let first = cycle.iter().enumerate().min_by_key(|(_, i)| **i).map(|(pos, _)| pos).unwrap_or_default();
+            cycle.rotate_left(first);
+            cycles.push(cycle);
+        }
+        for i in walk {
+            state[i] = 2;
+        }
+    }
+    cycles.sort_by_key(|c| c[0]);
+    cycles.into_iter()
+        .map(|c| Cycle { fk: fk.clone(), path: c.into_iter().map(|i| edges[i].0.clone()).collect() })
+        .collect()
+}
+
+/// Find the cycles of a self-referencing FK. The (key, reference) pairs of all the rows are read.
+pub fn cycles<C>(conn: &mut C, fk: &Rc<FkInfo>) -> Result<Vec<Cycle>>
+    where C: Queryable
+{
+    let not_null = fk.columns.iter()
+        .map(|c| format!("{c} IS NOT NULL"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    let query = format!("SELECT {}, {} FROM {}.{} WHERE {not_null} ORDER BY {}",
+        fk.ref_columns.join(", "), fk.columns.join(", "), fk.schema, fk.table, fk.ref_columns.join(", "));
+    let mut edges = Vec::new();
+    for row in conn.query::<Row, String>(query)? {
+        let [key, reference] = <[Vec<Value>; 2]>::try_from(split_row(row, fk.columns.len())).expect("2 groups of columns");
+        edges.push((key, reference));
+    }
+    Ok(find_cycles(edges, fk))
+}
+
+/// Break a cycle: set the FK columns of its closing row to NULL
+pub fn break_cycle<C>(conn: &mut C, cycle: &Cycle) -> Result<()>
+    where C: Queryable
+{
+    let fk = &cycle.fk;
+    let set_null = fk.columns.iter().map(|c| format!("{c}=NULL")).collect::<Vec<String>>().join(", ");
+    let where_key = fk.ref_columns.iter().map(|rc| format!("{rc}=?")).collect::<Vec<String>>().join(" AND ");
+    conn.exec_drop(format!("UPDATE {}.{} SET {set_null} WHERE {where_key}", fk.schema, fk.table), cycle.closing_row().to_vec())
+}
+
+/// Values of the FK columns of the rows of the subtrees: the invalid references of the roots and the keys of the nodes
+pub fn referencing_values(subtrees: &[OrphanSubtree]) -> Vec<Vec<Value>> {
+    let mut seen: HashSet<Vec<String>> = HashSet::new();
+    subtrees.iter()
+        .flat_map(|s| std::iter::once(&s.missing).chain(s.nodes.iter().map(|n| &n.key)))
+        .filter(|values| seen.insert(key_str(values)))
+        .cloned()
+        .collect()
+}
+
+/// Delete the rows of a subtree in a transaction, the deepest rows first
+pub fn delete_subtree(conn: &mut Conn, subtree: &OrphanSubtree) -> Result<()> {
+    let fk = &subtree.fk;
+    let mut nodes: Vec<&OrphanNode> = subtree.nodes.iter().collect();
+    nodes.sort_by_key(|n| std::cmp::Reverse(n.depth));
+    let mut tx = conn.start_transaction(TxOpts::default())?;
+    for batch in nodes.chunks(BATCH_SIZE) {
+        let query = format!("DELETE FROM {}.{} WHERE ({}) IN ({})", fk.schema, fk.table, fk.ref_columns.join(", "),
+            vec![format!("({})", vec!["?"; fk.ref_columns.len()].join(", ")); batch.len()].join(", "));
+        tx.exec_drop(query, batch.iter().flat_map(|n| n.key.clone()).collect::<Vec<Value>>())?;
+    }
+    tx.commit()
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use mysql::Value;
+
+    use super::{children_of, find_cycles, group, key_str, recursive_query, referencing_values, roots_query};
+    use crate::ddl;
+
+    #[test]
+    fn test_queries() {
+        let fk = &ddl::parse("CREATE TABLE category (id int PRIMARY KEY, parent_id int REFERENCES category (id));", "sch").fks[0];
+        assert_eq!(roots_query(fk), "SELECT a.id AS k0, a.parent_id AS m0 FROM sch.category a LEFT JOIN sch.category b ON a.parent_id=b.id WHERE a.parent_id IS NOT NULL AND b.id IS NULL");
+        assert_eq!(recursive_query(fk), "WITH RECURSIVE subtree (k0, r0, m0, depth) AS (\
+            SELECT o.k0, o.k0 AS r0, o.m0 AS m0, 0 AS depth FROM (SELECT a.id AS k0, a.parent_id AS m0 FROM sch.category a LEFT JOIN sch.category b ON a.parent_id=b.id WHERE a.parent_id IS NOT NULL AND b.id IS NULL) o \
+            UNION ALL SELECT a.id, s.r0, s.m0, s.depth + 1 FROM sch.category a JOIN subtree s ON a.parent_id=s.k0) \
+            SELECT * FROM subtree ORDER BY r0, depth");
+    }
+
+    #[test]
+    fn test_group() {
+        let fk = Rc::new(ddl::parse("CREATE TABLE category (id int PRIMARY KEY, parent_id int REFERENCES category (id));", "sch").fks.remove(0));
+        let v = |i: i32| vec![Value::from(i)];
+        let rows = vec![
+            (v(1), v(1), v(99), 0),
+            (v(3), v(1), v(99), 2),
+            (v(2), v(1), v(99), 1),
+            (v(5), v(5), v(98), 0),
+        ];
+        let res = group(rows, &fk);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].nodes.iter().map(|n| n.depth).collect::<Vec<u32>>(), vec![0, 1, 2]);
+        assert_eq!(res[0].depth(), 2);
+        assert_eq!(res[0].to_string(), "Orphaned subtree of 3 rows and depth 2 in table category, root id=1 references missing parent_id=99");
+        assert_eq!(res[1].depth(), 0);
+        assert_eq!(referencing_values(&res), vec![v(99), v(1), v(2), v(3), v(98), v(5)]);
+    }
+
+    #[test]
+    fn test_children_of() {
+        // Roots read with the text protocol, children read with the binary protocol
+        let b = |s: &str| vec![Value::from(s.as_bytes())];
+        let i = |i: i64| vec![Value::Int(i)];
+        let level = vec![(b("1"), b("1"), b("99")), (b("5"), b("5"), b("98"))];
+        let children = vec![(i(2), i(1)), (i(3), i(1)), (i(6), i(5)), (i(7), i(4))];
+        let next = children_of(&level, children);
+        assert_eq!(next, vec![(i(2), b("1"), b("99")), (i(3), b("1"), b("99")), (i(6), b("5"), b("98"))]);
+        assert_eq!(key_str(&b("12")), key_str(&i(12)));
+        assert_eq!(key_str(&[Value::UInt(12)]), key_str(&i(12)));
+        assert_ne!(key_str(&b("a")), key_str(&b("b")));
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        let fk = Rc::new(ddl::parse("CREATE TABLE employee (id int PRIMARY KEY, manager_id int REFERENCES employee (id));", "sch").fks.remove(0));
+        let v = |i: i32| vec![Value::from(i)];
+        // 1 -> 2 -> 1, 3 -> 1, 4 -> 4, 5 -> 6 -> 7 -> 5, 8 -> 99 (missing)
+        let edges = vec![(v(1), v(2)), (v(2), v(1)), (v(3), v(1)), (v(4), v(4)), (v(6), v(7)), (v(5), v(6)), (v(7), v(5)), (v(8), v(99))];
+        let res = find_cycles(edges, &fk);
+        let res: Vec<String> = res.iter().map(|c| c.to_string()).collect();
+        assert_eq!(res, vec![
+            "Reference cycle of 2 rows in table employee column manager_id: id=1 -> id=2 -> id=1",
+            "Reference cycle of 1 rows in table employee column manager_id: id=4 -> id=4",
+            "Reference cycle of 3 rows in table employee column manager_id: id=6 -> id=7 -> id=5 -> id=6",
+        ]);
+
+        let res = find_cycles(vec![(v(1), v(2)), (v(2), v(1))], &fk);
+        assert_eq!(res[0].closing_row(), &v(2)[..]);
+        assert!(find_cycles(vec![(v(1), v(2)), (v(2), v(3))], &fk).is_empty());
+    }
+}
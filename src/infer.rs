@@ -0,0 +1,299 @@
+//! Infer likely undeclared Foreign Keys, from column naming conventions and data
+
+use std::collections::HashMap;
+
+use mysql::*;
+use mysql::prelude::*;
+
+use crate::fk::{FkInfo, FkIndex};
+use crate::utils::schema_filter;
+
+/// Column definition from information_schema.COLUMNS
+#[derive(Debug, Clone)]
+pub struct ColumnDef {
+    pub schema: String,
+    pub table: String,
+    pub column: String,
+    pub data_type: String,
+    pub column_type: String,
+}
+
+impl ColumnDef {
+    pub fn new(row: (Value, Value, Value, Value, Value)) -> Self {
+        ColumnDef {
+            schema: String::from_value(row.0),
+            table: String::from_value(row.1),
+            column: String::from_value(row.2),
+            data_type: String::from_value(row.3),
+            column_type: String::from_value(row.4),
+        }
+    }
+
+    /// Same data type and signedness (display width is ignored)
+    pub fn type_matches(&self, other: &ColumnDef) -> bool {
+        self.data_type.eq_ignore_ascii_case(&other.data_type)
+            && self.column_type.to_lowercase().contains("unsigned") == other.column_type.to_lowercase().contains("unsigned")
+    }
+}
+
+/// A candidate virtual FK, measured against the data
+#[derive(Debug)]
+pub struct Candidate {
+    pub fk: FkInfo,
+    /// Number of non-NULL values in the child column
+    pub rows: u64,
+    /// Number of values not found in the parent column
+    pub orphans: u64,
+}
+
+impl Candidate {
+    /// Fraction of child values not found in the parent, None if the child column is empty
+    pub fn orphan_rate(&self) -> Option<f64> {
+        if self.rows == 0 {
+            None
+        } else {
+            Some(self.orphans as f64 / self.rows as f64)
+        }
+    }
+}
+
+/// Get all the columns of all the tables
+pub fn query_columns<T>(conn: &mut T, schema: Option<&String>) -> Result<Vec<ColumnDef>>
+    where T: Queryable
+{
+    let query = format!(
+        r"SELECT TABLE_SCHEMA, TABLE_NAME, COLUMN_NAME, DATA_TYPE, COLUMN_TYPE
+        FROM information_schema.COLUMNS
+        WHERE {}
+        ORDER BY TABLE_SCHEMA, TABLE_NAME, ORDINAL_POSITION",
+        schema_filter("TABLE_SCHEMA", schema));
+    conn.query_map(query, ColumnDef::new)
+}
+
+/// Get the columns having a single-column PRIMARY or UNIQUE index, as (schema, table, column)
+pub fn query_key_columns<T>(conn: &mut T, schema: Option<&String>) -> Result<Vec<(String, String, String)>>
+    where T: Queryable
+{
+    let query = format!(
+        r"SELECT TABLE_SCHEMA, TABLE_NAME, MIN(COLUMN_NAME)
+        FROM information_schema.STATISTICS
+        WHERE NON_UNIQUE=0 AND {}
+        GROUP BY TABLE_SCHEMA, TABLE_NAME, INDEX_NAME
+        HAVING COUNT(*)=1",
+        schema_filter("TABLE_SCHEMA", schema));
+    conn.query(query)
+}
+
+/// Possible table names for the stem of a column name, eg: category -> category, categorys, categoryes, categories
+fn table_names(stem: &str) -> Vec<String> {
+    let mut res = vec![stem.to_string(), format!("{stem}s"), format!("{stem}es")];
+    if let Some(base) = stem.strip_suffix('y') {
+        res.push(format!("{base}ies"));
+    }
+    res
+}
+
+/// Stem of a column named by convention <table>_id or <table>_fk
+fn column_stem(column: &str) -> Option<&str> {
+    let lower = column.len().checked_sub(3)?;
+    let suffix = column.get(lower..)?;
+    if suffix.eq_ignore_ascii_case("_id") || suffix.eq_ignore_ascii_case("_fk") {
+        Some(&column[..lower]).filter(|s| !s.is_empty())
+    } else {
+        None
+    }
+}
+
+/// Find candidate FKs using the column names, and matching types against PK/unique columns.
+/// Relationships already in `fk_idx` are not returned.
+pub fn find_candidates(columns: &[ColumnDef], key_columns: &[(String, String, String)], fk_idx: &FkIndex) -> Vec<FkInfo> {
+    let by_name: HashMap<(&str, &str, &str), &ColumnDef> = columns.iter()
+        .map(|c| ((c.schema.as_str(), c.table.as_str(), c.column.as_str()), c))
+        .collect();
+    let keys: Vec<&ColumnDef> = key_columns.iter()
+        .filter_map(|(s, t, c)| by_name.get(&(s.as_str(), t.as_str(), c.as_str())).copied())
+        .collect();
+
+    let mut res = Vec::new();
+    for col in columns {
+        let Some(stem) = column_stem(&col.column) else {
+            continue;
+        };
+        let names = table_names(&stem.to_lowercase());
+        let parent = keys.iter()
+            .filter(|k| k.schema == col.schema && col.type_matches(k))
+            .filter(|k| !(k.table == col.table && k.column == col.column))
+            .filter(|k| names.contains(&k.table.to_lowercase()) || k.column.eq_ignore_ascii_case(&col.column))
+            // Prefer the parent whose table matches the stem, then a column named id
+            .min_by_key(|k| (!names.contains(&k.table.to_lowercase()), !k.column.eq_ignore_ascii_case("id")));
+        let Some(parent) = parent else {
+            continue;
+        };
+
+        let declared = fk_idx.fks_by_table.get(&col.table)
+            .is_some_and(|fks| fks.iter().any(|f| f.schema == col.schema && f.columns == [col.column.clone()] && f.ref_table == parent.table));
+        if !declared {
+            res.push(FkInfo {
+                name: format!("vfk_{}_{}", col.table, col.column),
+                schema: col.schema.clone(),
+                table: col.table.clone(),
+                columns: vec![col.column.clone()],
+                ref_schema: parent.schema.clone(),
+                ref_table: parent.table.clone(),
+                ref_columns: vec![parent.column.clone()],
+                is_virtual: true,
+                ..Default::default()
+            });
+        }
+    }
+    res
+}
+
+/// Count the child values, and the child values not found in the parent
+pub fn measure<T>(conn: &mut T, fk: FkInfo) -> Result<Candidate>
+    where T: Queryable
+{
+    let query = format!(
+        r"SELECT COUNT(*), COUNT(b.{})
+        FROM {}.{} a
+        LEFT JOIN {}.{} b ON a.{}=b.{}
+        WHERE a.{} IS NOT NULL",
+        fk.ref_columns[0], fk.schema, fk.table, fk.ref_schema, fk.ref_table, fk.columns[0], fk.ref_columns[0], fk.columns[0]);
+    let (rows, found) = conn.query_first::<(u64, u64), String>(query)?.unwrap_or((0, 0));
+    Ok(Candidate { fk, rows, orphans: rows - found })
+}
+
+/// Sort candidates by orphan rate, lowest first. Candidates without data come last.
+pub fn rank(candidates: &mut [Candidate]) {
+    candidates.sort_by(|a, b| {
+        let rate = |c: &Candidate| c.orphan_rate().unwrap_or(f64::INFINITY);
+        rate(a).total_cmp(&rate(b)).then(b.rows.cmp(&a.rows))
+    });
+}
+
+/// A name as a TOML string, escaped
+fn toml_str(s: &str) -> String {
+    toml::Value::from(s).to_string()
+}
+
+/// Format the candidates as [[virtual_fk]] entries, to be pasted in the config file
+pub fn to_config(candidates: &[Candidate]) -> String {
+    let mut res = String::new();
+    for c in candidates {
+        match c.orphan_rate() {
+            Some(rate) => res += &format!("# orphan rate {:.2}% ({} of {} rows)\n", rate * 100.0, c.orphans, c.rows),
+            None => res += "# no data to measure the orphan rate\n",
+        }
+        res += &format!(
+            "[[virtual_fk]]\nschema = {}\ntable = {}\ncolumns = [{}]\nref_table = {}\nref_columns = [{}]\n\n",
+            toml_str(&c.fk.schema), toml_str(&c.fk.table), toml_str(&c.fk.columns[0]), toml_str(&c.fk.ref_table), toml_str(&c.fk.ref_columns[0]));
+    }
+    res
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn col(table: &str, column: &str, column_type: &str) -> ColumnDef {
+        ColumnDef {
+            schema: String::from("shop"),
+            table: String::from(table),
+            column: String::from(column),
+            data_type: String::from(column_type.split(['(', ' ']).next().unwrap()),
+            column_type: String::from(column_type),
+        }
+    }
+
+    fn key(table: &str, column: &str) -> (String, String, String) {
+        (String::from("shop"), String::from(table), String::from(column))
+    }
+
+    #[test]
+    fn test_column_stem() {
+        assert_eq!(column_stem("customer_id"), Some("customer"));
+        assert_eq!(column_stem("order_FK"), Some("order"));
+        assert_eq!(column_stem("_id"), None);
+        assert_eq!(column_stem("id"), None);
+        assert_eq!(column_stem("valid"), None);
+    }
+
+    #[test]
+    fn test_table_names() {
+        assert_eq!(table_names("category"), vec!["category", "categorys", "categoryes", "categories"]);
+        assert_eq!(table_names("box"), vec!["box", "boxs", "boxes"]);
+    }
+
+    #[test]
+    fn test_find_candidates() {
+        let columns = vec![
+            col("customers", "id", "int(11) unsigned"),
+            col("category", "id", "bigint(20)"),
+            col("orders", "id", "int(11)"),
+            col("orders", "customer_id", "int(10) unsigned"),
+            col("orders", "category_fk", "int(11)"), // type mismatch
+            col("orders", "unknown_id", "int(11)"),
+            col("lines", "order_id", "int(11)"),
+        ];
+        let keys = vec![key("customers", "id"), key("category", "id"), key("orders", "id")];
+        let fk_idx = FkIndex::from(vec![]);
+
+        let res = find_candidates(&columns, &keys, &fk_idx);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].table, "orders");
+        assert_eq!(res[0].columns, vec!["customer_id"]);
+        assert_eq!(res[0].ref_table, "customers");
+        assert_eq!(res[0].ref_columns, vec!["id"]);
+        assert!(res[0].is_virtual);
+        assert_eq!(res[1].table, "lines");
+        assert_eq!(res[1].ref_table, "orders");
+    }
+
+    #[test]
+    fn test_find_candidates_skips_declared() {
+        let columns = vec![
+            col("orders", "id", "int(11)"),
+            col("lines", "order_id", "int(11)"),
+        ];
+        let keys = vec![key("orders", "id")];
+        let declared = FkInfo::new((
+            Value::from("lines_ibfk_1"), Value::from("shop"), Value::from("lines"),
+            Value::from("order_id"), Value::from("orders"), Value::from("id")
+        ));
+        let fk_idx = FkIndex::from(vec![declared]);
+        assert!(find_candidates(&columns, &keys, &fk_idx).is_empty());
+    }
+
+    #[test]
+    fn test_rank_and_to_config() {
+        let fk = |table: &str| FkInfo {
+            name: format!("vfk_{table}_x_id"),
+            schema: String::from("shop"),
+            table: String::from(table),
+            columns: vec![String::from("x_id")],
+            ref_schema: String::from("shop"),
+            ref_table: String::from("x"),
+            ref_columns: vec![String::from("id")],
+            is_virtual: true,
+            ..Default::default()
+        };
+        let mut candidates = vec![
+            Candidate { fk: fk("empty"), rows: 0, orphans: 0 },
+            Candidate { fk: fk("bad"), rows: 10, orphans: 5 },
+            Candidate { fk: fk("good"), rows: 10, orphans: 0 },
+        ];
+        rank(&mut candidates);
+        assert_eq!(candidates[0].fk.table, "good");
+        assert_eq!(candidates[1].fk.table, "bad");
+        assert_eq!(candidates[2].fk.table, "empty");
+
+        let res = to_config(&candidates[1..2]);
+        assert_eq!(res, "# orphan rate 50.00% (5 of 10 rows)\n[[virtual_fk]]\nschema = \"shop\"\ntable = \"bad\"\ncolumns = [\"x_id\"]\nref_table = \"x\"\nref_columns = [\"id\"]\n\n");
+
+        // Quoted identifiers may hold any character
+        let odd = Candidate { fk: FkInfo { table: String::from("we\"ird\\"), ..fk("odd") }, rows: 0, orphans: 0 };
+        let config: toml::Value = toml::from_str(&to_config(&[odd])).unwrap();
+        assert_eq!(config["virtual_fk"][0]["table"].as_str(), Some("we\"ird\\"));
+    }
+}
@@ -1,46 +1,603 @@
-use mysql::{Conn, Opts, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, Result, Row, Value};
 
 pub mod args;
 use args::AppArgs;
 
 pub mod fk;
-use fk::{FkInfo, FkIndex};
+use fk::{FkIndex, FkInfo};
 
 pub mod fkchecker;
 use fkchecker::FkChecker;
 
 pub mod datadumper;
 
+pub mod config;
+use config::Config;
+
+pub mod infer;
+
+pub mod graph;
+use graph::FkGraph;
+
+pub mod report;
+use report::{Report, Severity, ViolationKind};
+
+pub mod diagram;
+use diagram::DiagramFormat;
+
+pub mod table;
+use table::TableInfo;
+
+pub mod server;
+use server::ServerVersion;
+
+pub mod ddl;
+
+pub mod dumpcheck;
+use dumpcheck::DumpChecker;
+
+pub mod snapshot;
+use snapshot::Snapshot;
+
+pub mod source;
+use source::{InformationSchema, MetadataSource};
+
+pub mod drift;
+
+pub mod lint;
+
+pub mod advisor;
+
+pub mod promote;
+
+pub mod hierarchy;
+
+pub mod embedded;
+
+pub mod trigger;
+use trigger::{GuardTrigger, TriggerCommand};
+
 #[macro_use]
 pub mod utils;
-use utils::{exit_on_err, continue_on_err};
 
 fn get_conn(args: &AppArgs) -> Result<Conn> {
-    println!("Connecting to {}", args.db_url);
-    let res = Conn::new(Opts::from_url(args.db_url.as_str())?)?;
+    let db_url = args.db_url.as_deref().unwrap_or_default();
+    println!("Connecting to {}", db_url);
+    let res = Conn::new(Opts::from_url(db_url)?)?;
 
     let version_numbers = res.server_version();
     println!("MySQL server version: {}.{}.{}", version_numbers.0, version_numbers.1, version_numbers.2);
     Ok(res)
 }
 
-fn check_fks(mut conn: &mut Conn, args: AppArgs) {
-    let fk_constraints = FkIndex::from(exit_on_err!(FkInfo::query_fk_constraints(conn, args.schema.as_ref()), "Could not get list of FK constraints"));
+/// Merge the FK constraints of `source` with the virtual FKs declared in the config file, and set their scopes and alive predicates
+fn merge_with_config(source: &mut dyn MetadataSource, config: Option<&mut Config>, args: &AppArgs) -> Result<FkIndex> {
+    let Some(config) = config else {
+        return source::merge(&mut [source], args.schema.as_ref());
+    };
+    let fk_idx = source::merge(&mut [source, &mut *config], args.schema.as_ref())?;
+    Ok(config.apply_to(fk_idx)?)
+}
+
+/// The FK constraints of a run, the tables of the schema snapshot loaded with --load-schema and the config file
+struct DiscoveredSchema {
+    fk_idx: FkIndex,
+    /// None without --load-schema, the tables are then queried from information_schema when needed
+    snapshot_tables: Option<Vec<TableInfo>>,
+    config: Option<Config>,
+}
+
+/// Get the FK constraints from the database or from the schema snapshot, plus the virtual FKs declared in the config file.
+/// Saves them in a schema snapshot if asked.
+fn get_fk_index(conn: &mut Conn, args: &AppArgs) -> Result<DiscoveredSchema> {
+    let mut config = args.config.as_deref().map(Config::load).transpose()?;
+    let (fk_idx, snapshot_tables, snapshot_version) = match &args.load_schema {
+        Some(path) => {
+            let mut snapshot = Snapshot::load(path)?;
+            println!("Loaded schema snapshot of MySQL server {}", snapshot.server_version.as_deref().unwrap_or("unknown"));
+            let fk_idx = merge_with_config(&mut snapshot, config.as_mut(), args)?;
+            (fk_idx, Some(snapshot.tables), snapshot.server_version)
+        }
+        None => (merge_with_config(&mut InformationSchema { conn: &mut *conn }, config.as_mut(), args)?, None, None),
+    };
+    if args.save_schema.is_some() {
+        // The tables of a loaded snapshot are saved again, the database is not read
+        let (tables, server_version) = match &snapshot_tables {
+            Some(tables) => (tables.clone(), snapshot_version),
+            None => {
+                let (major, minor, patch) = conn.server_version();
+                (TableInfo::query_tables(conn, args.schema.as_ref())?, Some(format!("{major}.{minor}.{patch}")))
+            }
+        };
+        save_schema(args, &fk_idx, tables, server_version)?;
+    }
+    Ok(DiscoveredSchema { fk_idx, snapshot_tables, config })
+}
 
-    println!("Found {} Foreign Key Constraints to check...", fk_constraints.fks.len());
+/// Write the schema snapshot if asked
+fn save_schema(args: &AppArgs, fk_idx: &FkIndex, tables: Vec<TableInfo>, server_version: Option<String>) -> std::io::Result<()> {
+    if let Some(path) = &args.save_schema {
+        Snapshot::new(fk_idx, tables, server_version).save(path)?;
+        println!("Schema snapshot written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Write the FK graph if asked, with the orphan counts of the report
+fn write_graph(args: &AppArgs, fk_idx: &FkIndex, report: Option<&Report>) {
+    if let Some(graph_path) = &args.graph {
+        let format = DiagramFormat::from_path(graph_path).unwrap_or(DiagramFormat::Dot);
+        exit_on_err!(fs::write(graph_path, diagram::render(format, fk_idx, report)), "Could not write FK graph");
+        println!("FK graph written to {}", graph_path.display());
+    }
+}
+
+/// Print the structural problems and the missing indexes of the FK constraints, if asked
+fn structural_checks(args: &AppArgs, fk_idx: &FkIndex, tables: &[TableInfo]) {
+    if args.lint {
+        let lints = lint::lint(fk_idx, tables);
+        println!("Found {} structural problems in Foreign Key Constraints", lints.len());
+        for lint in lints.iter() {
+            println!("{lint}");
+        }
+    }
+    if args.advise_indexes || args.index_file.is_some() {
+        let advices = advisor::advise(fk_idx, tables);
+        println!("Found {} Foreign Key Constraints lacking an index", advices.len());
+        for advice in advices.iter() {
+            println!("{advice}");
+        }
+        if let Some(index_path) = &args.index_file {
+            let statements: Vec<String> = advices.iter().flat_map(|a| a.create_statements()).collect();
+            exit_on_err!(fs::write(index_path, statements.join("\n") + "\n"), "Could not write CREATE INDEX statements");
+            println!("CREATE INDEX statements written to {}", index_path.display());
+        }
+    }
+}
+
+/// The definitions of the tables of the FK constraints, those of the schema snapshot with --load-schema
+fn query_fk_tables(conn: &mut Conn, args: &AppArgs, discovered: &DiscoveredSchema) -> Result<Vec<TableInfo>> {
+    if let Some(tables) = &discovered.snapshot_tables {
+        return Ok(tables.clone());
+    }
+    // The referenced tables of cross-schema FKs are in other schemas
+    let schema = args.schema.as_ref().filter(|_| !discovered.fk_idx.fks.iter().any(|fk| fk.is_cross_schema()));
+    TableInfo::query_tables(conn, schema)
+}
+
+/// The tables not enforcing FK constraints, taken from the schema snapshot with --load-schema
+fn query_unprotected(conn: &mut Conn, args: &AppArgs, discovered: &DiscoveredSchema) -> Result<Vec<TableInfo>> {
+    match &discovered.snapshot_tables {
+        Some(tables) => Ok(tables.iter()
+            .filter(|t| !t.enforces_fks() && args.schema.as_ref().is_none_or(|s| *s == t.schema))
+            .cloned()
+            .collect()),
+        None => TableInfo::query_unprotected(conn, args.schema.as_ref()),
+    }
+}
+
+/// Warn about the tables whose FK constraints are silently dropped
+fn report_unprotected<'t>(tables: impl IntoIterator<Item = &'t TableInfo>) {
+    for table in tables {
+        let engine = table.engine.as_deref().unwrap_or("unknown engine");
+        let kind = if table.partitioned { format!("partitioned {engine}") } else { engine.to_string() };
+        println!("WARNING: Table {} in schema {} is a {kind} table, its Foreign Key Constraints are not enforced", table.name, table.schema);
+    }
+}
+
+/// Check an FK one partition of its table at a time, returns the number of invalid references
+fn check_by_partition(checker: &FkChecker, fk: &FkInfo, fk_idx: &FkIndex, conn: &mut Conn) -> Result<usize> {
+    let partitions = TableInfo::query_partitions(conn, &fk.schema, &fk.table)?;
+    let mut count = 0;
+    for partition in partitions.iter() {
+        println!("Checking partition {partition}");
+        count += checker.check_partition::<Row, Conn>(fk, Some(partition), fk_idx, conn)?.len();
+    }
+    Ok(count)
+}
+
+/// Check a self-referencing FK: the rows referencing a missing row are reported with their descendants,
+/// and deleted with them as a unit. Returns the number of roots and of descendants.
+fn check_hierarchy(checker: &FkChecker, fk: &Rc<FkInfo>, fk_idx: &FkIndex, conn: &mut Conn, server: &ServerVersion) -> Result<(usize, usize)> {
+    let subtrees = hierarchy::orphan_subtrees(conn, fk, server.has_recursive_cte())?;
+    for subtree in subtrees.iter() {
+        println!("{subtree}");
+    }
+    if checker.dump_invalid_rows && !subtrees.is_empty() {
+        checker.dump_referencing(fk, &fk.name, &hierarchy::referencing_values(&subtrees), fk_idx, conn)?;
+    }
+    if checker.auto_delete {
+        for subtree in subtrees.iter() {
+            hierarchy::delete_subtree(conn, subtree)?;
+        }
+    }
+    let rows: usize = subtrees.iter().map(|s| s.nodes.len()).sum();
+    Ok((subtrees.len(), rows - subtrees.len()))
+}
+
+/// Find the reference cycles of a self-referencing FK, and break them if asked. Returns the number of rows in cycles.
+fn check_cycles(checker: &FkChecker, fk: &Rc<FkInfo>, fk_idx: &FkIndex, conn: &mut Conn, break_cycles: bool) -> Result<usize> {
+    let cycles = hierarchy::cycles(conn, fk)?;
+    for cycle in cycles.iter() {
+        println!("{cycle}");
+    }
+    if checker.dump_invalid_rows && !cycles.is_empty() {
+        let keys: Vec<Vec<Value>> = cycles.iter().flat_map(|c| c.path.iter().cloned()).collect();
+        checker.dump_keys(fk, &format!("{}_cycles", fk.name), &keys, fk_idx, conn)?;
+    }
+    if break_cycles {
+        for cycle in cycles.iter() {
+            hierarchy::break_cycle(conn, cycle)?;
+            println!("Reference of {} set to NULL", hierarchy::values_desc(&fk.ref_columns, cycle.closing_row()));
+        }
+    }
+    Ok(cycles.iter().map(|c| c.path.len()).sum())
+}
+
+/// Check the references embedded in JSON documents or delimited lists: the dangling ones are removed from their value
+/// with --remove-dangling, or their rows deleted with --auto-delete. Returns the number of dangling references.
+fn check_embedded(checker: &FkChecker, fk: &Rc<FkInfo>, fk_idx: &FkIndex, conn: &mut Conn, server: &ServerVersion, remove_dangling: bool) -> Result<usize> {
+    let dangling = embedded::dangling_refs(conn, fk, server.has_json_table())?;
+    for r in dangling.refs.iter() {
+        println!("{}", dangling.describe(r));
+    }
+    if checker.dump_invalid_rows && !dangling.refs.is_empty() {
+        checker.dump_rows(fk, &dangling.primary_key, &fk.name, &dangling.keys(), fk_idx, conn)?;
+    }
+    if remove_dangling {
+        embedded::remove_refs(conn, &dangling)?;
+    } else if checker.auto_delete {
+        embedded::delete_rows(conn, &dangling)?;
+    }
+    Ok(dangling.refs.len())
+}
 
-    let checker = exit_on_err!(FkChecker::new(args.auto_delete, args.dump_invalid_rows, args.dump_loc), "Could not initialise FK checker");
+/// Report the rows of the parent tables referenced by none of their incoming constraints, eg: customers without orders nor addresses.
+/// The lookup tables are skipped, as are the tables referenced by embedded references.
+fn report_unreferenced(checker: &FkChecker, fk_idx: &FkIndex, lookup_tables: &[String], report: &mut Report, conn: &mut Conn) {
+    let mut parents: BTreeMap<(&str, &str), Vec<Rc<FkInfo>>> = BTreeMap::new();
+    for fk in fk_idx.fks_by_ref_table.values().flatten() {
+        parents.entry((&fk.ref_schema, &fk.ref_table)).or_default().push(fk.clone());
+    }
+    for ((schema, table), fks) in parents.iter() {
+        if lookup_tables.iter().any(|t| t == table || *t == format!("{schema}.{table}")) {
+            continue;
+        }
+        if let Some(fk) = fks.iter().find(|fk| fk.is_embedded()) {
+            println!("Skipping unreferenced rows of table {schema}.{table}, referenced by {}", fk.name);
+            continue;
+        }
+        let (count, listed) = continue_on_err!(checker.check_unreferenced(fks, conn), "Could not find unreferenced rows");
+        let ref_columns = &fks[0].ref_columns;
+        // A cleanup report, the rows are valid: not a failure of the run
+        if let Some(violation) = report.add_with_severity(&format!("{table}_unreferenced"), table, ref_columns, ViolationKind::Unreferenced, count, Severity::Warning) {
+            let names: Vec<&str> = fks.iter().map(|fk| fk.name.as_str()).collect();
+            println!("{violation}, through none of {}", names.join(", "));
+            for key in listed.iter() {
+                println!("    {}", hierarchy::values_desc(ref_columns, key));
+            }
+            if count > listed.len() {
+                println!("    and {} more", count - listed.len());
+            }
+        }
+    }
+}
+
+/// Check the FK constraints and assertions, the violations found are added to `report`
+fn check_fks(conn: &mut Conn, args: AppArgs, report: &mut Report) {
+    let discovered = exit_on_err!(get_fk_index(conn, &args), "Could not get list of FK constraints");
+
+    let unprotected = exit_on_err!(query_unprotected(conn, &args, &discovered), "Could not get list of tables");
+    report_unprotected(&unprotected);
+
+    if args.lint || args.advise_indexes || args.index_file.is_some() {
+        let tables = exit_on_err!(query_fk_tables(conn, &args, &discovered), "Could not get table definitions");
+        structural_checks(&args, &discovered.fk_idx, &tables);
+    }
+    let fk_constraints = discovered.fk_idx;
+    let assertions = discovered.config.map(|c| c.assertions).unwrap_or_default();
+
+    if !args.no_check {
+        println!("Found {} Foreign Key Constraints and {} assertions to check...", fk_constraints.fks.len(), assertions.len());
+
+        let checker = exit_on_err!(FkChecker::new(args.auto_delete, args.dump_invalid_rows, args.dump_loc.clone()), "Could not initialise FK checker");
+        let server = exit_on_err!(ServerVersion::query(conn), "Could not get the server version");
+        // Parents first, so that rows deleted in a table are seen when checking its children
+        let order = FkGraph::new(&fk_constraints).topological_order();
+        for fk in order.iter().filter_map(|t| fk_constraints.fks_by_table.get(t)).flatten() {
+            println!("Checking Foreign Key constraint {fk}");
+            if fk.is_embedded() {
+                let count = continue_on_err!(check_embedded(&checker, fk, &fk_constraints, conn, &server, args.remove_dangling), "Could not check embedded references");
+                if let Some(violation) = report.add(fk, ViolationKind::MissingParent, count) {
+                    println!("{violation}");
+                }
+                continue;
+            }
+            let cross_scope = continue_on_err!(checker.check_scope::<Row, Conn>(fk, &fk_constraints, conn), "Could not check the scope of Foreign Key Constraint");
+            if let Some(violation) = report.add(fk, ViolationKind::CrossScope, cross_scope.len()) {
+                println!("{violation}");
+            }
+            let soft_deleted = continue_on_err!(checker.check_alive::<Row, Conn>(fk, &fk_constraints, conn), "Could not check the soft-deleted parents of Foreign Key Constraint");
+            if let Some(violation) = report.add(fk, ViolationKind::SoftDeletedParent, soft_deleted.len()) {
+                println!("{violation}");
+            }
+            if let Some(cardinality) = fk.cardinality {
+                let parents = continue_on_err!(checker.check_cardinality(fk, conn), "Could not check the cardinality of Foreign Key Constraint");
+                let too_few = parents.iter().filter(|(_, count)| *count < cardinality.min).count();
+                for (kind, count) in [(ViolationKind::TooFewChildren, too_few), (ViolationKind::TooManyChildren, parents.len() - too_few)] {
+                    if let Some(violation) = report.add_rows(&fk.name, &fk.ref_table, &fk.ref_columns, kind, count) {
+                        println!("{violation}, expected {cardinality} rows of table {} each", fk.table);
+                    }
+                }
+            }
+            let not_valid = continue_on_err!(checker.check_validity::<Row, Conn>(fk, &fk_constraints, conn), "Could not check the validity periods of Foreign Key Constraint");
+            if let Some(violation) = report.add(fk, ViolationKind::NotValidAtTime, not_valid.len()) {
+                println!("{violation}");
+            }
+            // A polymorphic branch only covers some rows of the table, it is checked like any FK
+            if fk.is_self_referencing() && fk.discriminator.is_none() {
+                let (roots, descendants) = continue_on_err!(check_hierarchy(&checker, fk, &fk_constraints, conn, &server), "Could not check Foreign Key Constraint");
+                let cycles = continue_on_err!(check_cycles(&checker, fk, &fk_constraints, conn, args.break_cycles), "Could not check reference cycles");
+                for (kind, count) in [(ViolationKind::MissingParent, roots), (ViolationKind::OrphanedSubtree, descendants), (ViolationKind::Cycle, cycles)] {
+                    if let Some(violation) = report.add(fk, kind, count) {
+                        println!("{violation}");
+                    }
+                }
+                continue;
+            }
+            let partitioned = args.by_partition && unprotected.iter().any(|t| t.partitioned && t.schema == fk.schema && t.name == fk.table);
+            let res = if partitioned {
+                check_by_partition(&checker, fk, &fk_constraints, conn)
+            } else {
+                checker.check::<Row, Conn>(fk, &fk_constraints, conn).map(|rows| rows.len())
+            };
+            let count = continue_on_err!(res, "Could not check Foreign Key Constraint");
+            if let Some(violation) = report.add(fk, ViolationKind::MissingParent, count) {
+                println!("{violation}");
+            }
+        }
+        for poly in fk_constraints.polymorphic() {
+            println!("Checking the types of polymorphic relationship {poly}");
+            let unknown = continue_on_err!(checker.check_types::<Row, Conn>(&poly, &fk_constraints, conn), "Could not check the types of polymorphic relationship");
+            if let Some(violation) = report.add_rows(&poly.name, &poly.table, std::slice::from_ref(&poly.type_column), ViolationKind::UnknownType, unknown.len()) {
+                println!("{violation}");
+            }
+        }
+        for assertion in assertions.iter() {
+            println!("Checking assertion {assertion}");
+            let count = continue_on_err!(checker.check_assertion(assertion, conn), "Could not check assertion");
+            if let Some(violation) = report.add_with_severity(&assertion.name, "", &[], ViolationKind::FailedAssertion, count, assertion.severity) {
+                match violation.severity {
+                    Severity::Error => println!("{violation}"),
+                    Severity::Warning => println!("WARNING: {violation}"),
+                }
+            }
+        }
+        if args.unreferenced {
+            report_unreferenced(&checker, &fk_constraints, &args.lookup_tables, report, conn);
+        }
+    }
+
+    write_graph(&args, &fk_constraints, if args.no_check { None } else { Some(report) });
+}
+
+/// Offline mode: the FK constraints come from the CREATE TABLE statements of a DDL file,
+/// the rows of its INSERT statements are checked against them
+fn analyze_ddl(ddl_path: &Path, args: AppArgs, report: &mut Report) {
+    // Tables are in the schema given with --schema, or named after the file, until a USE statement
+    let default_schema = args.schema.clone()
+        .or_else(|| ddl_path.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    let mut ddl = exit_on_err!(ddl::parse_file(ddl_path, &default_schema), "Could not parse DDL file");
+    let mut config = exit_on_err!(args.config.as_deref().map(Config::load).transpose(), "Could not load config file");
+    let fk_constraints = exit_on_err!(merge_with_config(&mut ddl, config.as_mut(), &args), "Invalid virtual foreign keys in config file");
+    exit_on_err!(save_schema(&args, &fk_constraints, ddl.tables.clone(), None), "Could not save schema snapshot");
+
+    println!("Found {} tables and {} Foreign Key Constraints in {}", ddl.tables.len(), fk_constraints.fks.len(), ddl_path.display());
+    report_unprotected(ddl.tables.iter().filter(|t| !t.enforces_fks()));
     for fk in fk_constraints.fks.iter() {
-        println!("Checking Foreign Key constraint {fk}");
-        let res = checker.check::<u32, Conn>(&fk, &fk_constraints, &mut conn);
-        let res = continue_on_err!(res, "Could not check Foreign Key Constraint");
-        if res.len() > 0 {
-            println!("{} invalid foreign references found in table {} column {}", res.len(), fk.table, fk.column);
+        println!("{fk}");
+    }
+    structural_checks(&args, &fk_constraints, &ddl.tables);
+    if args.no_check {
+        return write_graph(&args, &fk_constraints, None);
+    }
+
+    if args.auto_delete {
+        println!("WARNING: --auto-delete is ignored in offline mode");
+    }
+    let checker = exit_on_err!(FkChecker::new(false, args.dump_invalid_rows, args.dump_loc.clone()), "Could not initialise FK checker");
+    let max_keys = args.max_keys_in_memory.unwrap_or(dumpcheck::DEFAULT_MAX_KEYS_IN_MEMORY);
+    println!("Checking the rows of {}...", ddl_path.display());
+    let dump_checker = DumpChecker::new(&ddl, &fk_constraints, &checker, max_keys);
+    *report = exit_on_err!(dump_checker.check(ddl_path, &default_schema), "Could not check the rows of the dump");
+    for violation in report.violations.iter() {
+        println!("{violation}");
+    }
+    write_graph(&args, &fk_constraints, Some(report));
+}
+
+/// Exit code of a run without drift nor violation of severity error
+pub const EXIT_OK: i32 = 0;
+
+/// Exit code of a --drift run which could not compare, eg: unreadable expected constraints or no connection,
+/// so that CI does not pass when the check never ran
+pub const EXIT_ERROR: i32 = 1;
+
+/// Exit code of a run finding a drift
+pub const EXIT_DRIFT: i32 = 2;
+
+/// Exit code of a check finding violations of severity error: invalid references, failed assertions of severity error...
+/// Warnings (assertions of severity warning, unreferenced rows) do not change the exit code.
+pub const EXIT_VIOLATIONS: i32 = 3;
+
+/// EXIT_VIOLATIONS if the report has errors, for CI
+fn report_status(report: &Report) -> i32 {
+    if !report.has_errors() {
+        return EXIT_OK;
+    }
+    let errors = report.violations.iter().filter(|v| v.severity == Severity::Error).count();
+    println!("{errors} violations found");
+    EXIT_VIOLATIONS
+}
+
+/// Compare the expected FK constraints (schema snapshot or config file) with the constraints of the database
+/// Returns the exit code: EXIT_DRIFT if a drift is found
+fn check_drift(conn: &mut Conn, expected_path: &Path, args: AppArgs) -> i32 {
+    let is_snapshot = expected_path.extension().is_some_and(|ext| ext == "json");
+    let mut expected: Box<dyn MetadataSource> = if is_snapshot {
+        Box::new(exit_on_err!(Snapshot::load(expected_path), "Could not load schema snapshot", EXIT_ERROR))
+    } else {
+        Box::new(exit_on_err!(Config::load(expected_path), "Could not load config file", EXIT_ERROR))
+    };
+    let expected = exit_on_err!(source::merge(&mut [expected.as_mut()], args.schema.as_ref()), "Invalid expected constraints", EXIT_ERROR);
+    let actual = exit_on_err!(source::merge(&mut [&mut InformationSchema { conn }], args.schema.as_ref()), "Could not get list of FK constraints", EXIT_ERROR);
+
+    println!("Comparing {} expected Foreign Key Constraints with the {} of the database...", expected.fks.len(), actual.fks.len());
+    // A config file only declares the virtual FKs, not the constraints already in the database
+    let drifts = drift::compare(&expected, &actual, is_snapshot);
+    for drift in drifts.iter() {
+        println!("{drift}");
+    }
+    if drifts.is_empty() {
+        println!("No drift found");
+        EXIT_OK
+    } else {
+        println!("{} drifts found", drifts.len());
+        EXIT_DRIFT
+    }
+}
+
+/// Ask a yes/no question on the terminal, no by default
+fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Turn the virtual FKs without orphans nor structural problems into real constraints:
+/// print the ALTER TABLE statements, write them in a migration file or run them
+fn promote_fks(conn: &mut Conn, args: AppArgs) {
+    let discovered = exit_on_err!(get_fk_index(conn, &args), "Could not get list of FK constraints");
+    let fk_constraints = &discovered.fk_idx;
+    // Polymorphic branches only reference their table for some rows, embedded references are not columns
+    let virtual_fks: Vec<&Rc<FkInfo>> = fk_constraints.fks.iter().filter(|fk| fk.is_virtual && fk.can_be_constraint()).collect();
+    if virtual_fks.is_empty() {
+        return println!("No virtual Foreign Keys to promote");
+    }
+    let tables = exit_on_err!(query_fk_tables(conn, &args, &discovered), "Could not get table definitions");
+    let checker = exit_on_err!(FkChecker::new(false, args.dump_invalid_rows, args.dump_loc.clone()), "Could not initialise FK checker");
+
+    println!("Verifying {} virtual Foreign Keys...", virtual_fks.len());
+    let mut ready: Vec<Rc<FkInfo>> = Vec::new();
+    for fk in virtual_fks {
+        let res = checker.check::<Row, Conn>(fk, fk_constraints, conn);
+        let orphans = continue_on_err!(res, "Could not check Foreign Key Constraint").len();
+        let promotion = promote::verify(fk, &tables, orphans);
+        println!("{promotion}");
+        if promotion.is_ready() {
+            ready.push(promotion.fk);
+        }
+    }
+    if ready.is_empty() {
+        return println!("No virtual Foreign Keys can be promoted");
+    }
+
+    println!("{} virtual Foreign Keys can be promoted:", ready.len());
+    for fk in ready.iter() {
+        println!("{}", promote::add_constraint(fk));
+    }
+    if let Some(dir) = &args.migration_dir {
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let (name, content) = promote::migration(args.migration_format, &ready, unix_time);
+        let path = dir.join(name);
+        exit_on_err!(fs::create_dir_all(dir).and_then(|_| fs::write(&path, content)), "Could not write migration file");
+        println!("Migration written to {}", path.display());
+    }
+    if args.execute && confirm(&format!("Run the {} ALTER TABLE statements?", ready.len())) {
+        for fk in ready.iter() {
+            continue_on_err!(conn.query_drop(promote::add_constraint(fk)), "Could not create Foreign Key Constraint");
+            println!("Created Foreign Key Constraint {}", fk.name);
+        }
+    }
+}
+
+/// Install, list or remove the guard triggers of the virtual FKs
+fn guard_triggers(conn: &mut Conn, command: TriggerCommand, args: AppArgs) {
+    if command == TriggerCommand::Install {
+        let fk_constraints = exit_on_err!(get_fk_index(conn, &args), "Could not get list of FK constraints").fk_idx;
+        // The references embedded in a column value can not be matched by a trigger query
+        let virtual_fks: Vec<&Rc<FkInfo>> = fk_constraints.fks.iter().filter(|fk| fk.is_virtual && !fk.is_embedded()).collect();
+        println!("Installing the guard triggers of {} virtual Foreign Keys...", virtual_fks.len());
+        for trigger in virtual_fks.iter().flat_map(|fk| trigger::guard_triggers(fk)) {
+            // Replaces the trigger of a previous install
+            let res = conn.query_drop(trigger.drop_statement()).and_then(|_| conn.query_drop(trigger.create_statement()));
+            continue_on_err!(res, "Could not install guard trigger");
+            println!("Installed {trigger}");
+        }
+        return;
+    }
+
+    let triggers = exit_on_err!(GuardTrigger::query_triggers(conn, args.schema.as_ref()), "Could not get list of triggers");
+    println!("Found {} guard triggers", triggers.len());
+    for trigger in triggers.iter() {
+        if command == TriggerCommand::Remove {
+            continue_on_err!(conn.query_drop(trigger.drop_statement()), "Could not remove guard trigger");
+            println!("Removed {trigger}");
+        } else {
+            println!("{trigger}");
         }
     }
 }
 
-pub fn run(args: AppArgs) {
-    let mut conn = exit_on_err!(get_conn(&args), "Could not connect to MySQL");
-    check_fks(&mut conn, args);
+fn infer_fks(conn: &mut Conn, args: AppArgs) {
+    let fk_constraints = exit_on_err!(get_fk_index(conn, &args), "Could not get list of FK constraints").fk_idx;
+    let columns = exit_on_err!(infer::query_columns(conn, args.schema.as_ref()), "Could not get list of columns");
+    let key_columns = exit_on_err!(infer::query_key_columns(conn, args.schema.as_ref()), "Could not get list of unique keys");
+
+    let found = infer::find_candidates(&columns, &key_columns, &fk_constraints);
+    println!("Found {} candidate Foreign Keys, measuring orphan rates...", found.len());
+
+    let mut candidates = Vec::with_capacity(found.len());
+    for fk in found {
+        candidates.push(continue_on_err!(infer::measure(conn, fk), "Could not measure candidate Foreign Key"));
+    }
+    infer::rank(&mut candidates);
+    print!("{}", infer::to_config(&candidates));
+}
+
+/// Run the command given by the arguments, returns the exit code of the process
+pub fn run(args: AppArgs) -> i32 {
+    let mut report = Report::default();
+    if let Some(ddl_path) = args.ddl.clone() {
+        analyze_ddl(&ddl_path, args, &mut report);
+        return report_status(&report);
+    }
+    if let Some(expected_path) = args.drift.clone() {
+        let mut conn = exit_on_err!(get_conn(&args), "Could not connect to MySQL", EXIT_ERROR);
+        return check_drift(&mut conn, &expected_path, args);
+    }
+    let mut conn = exit_on_err!(get_conn(&args), "Could not connect to MySQL", EXIT_OK);
+    if let Some(command) = args.guard_triggers {
+        guard_triggers(&mut conn, command, args);
+    } else if args.promote {
+        promote_fks(&mut conn, args);
+    } else if args.infer {
+        infer_fks(&mut conn, args);
+    } else {
+        check_fks(&mut conn, args, &mut report);
+    }
+    report_status(&report)
 }
@@ -0,0 +1,142 @@
+//! Structural problems of the FK constraints: column definitions differing between both sides,
+//! referenced columns without a unique index. They make the check JOIN behave surprisingly.
+
+use std::fmt::Display;
+
+use crate::fk::{FkIndex, FkInfo};
+use crate::table::{ColumnInfo, TableInfo};
+
+/// What is wrong with a constraint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    TypeMismatch,
+    SignednessMismatch,
+    LengthMismatch,
+    CharsetMismatch,
+    CollationMismatch,
+    /// The referenced columns can match several rows
+    NotUnique,
+    /// Column or table not found
+    UnknownColumn,
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintKind::TypeMismatch => write!(f, "type mismatch"),
+            LintKind::SignednessMismatch => write!(f, "signedness mismatch"),
+            LintKind::LengthMismatch => write!(f, "length mismatch"),
+            LintKind::CharsetMismatch => write!(f, "charset mismatch"),
+            LintKind::CollationMismatch => write!(f, "collation mismatch"),
+            LintKind::NotUnique => write!(f, "referenced columns not covered by a unique index"),
+            LintKind::UnknownColumn => write!(f, "unknown column"),
+        }
+    }
+}
+
+/// A structural problem of a constraint
+#[derive(Debug)]
+pub struct Lint {
+    pub constraint: String,
+    pub kind: LintKind,
+    /// The columns involved, eg: "child.parent_id int unsigned, parent.id bigint"
+    pub detail: String,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}, {}", self.constraint, self.kind, self.detail)
+    }
+}
+
+fn find_table<'t>(tables: &'t [TableInfo], schema: &str, name: &str) -> Option<&'t TableInfo> {
+    tables.iter().find(|t| t.schema == schema && t.name == name)
+}
+
+/// Mismatches between a column and the column it references
+fn column_lints(fk: &FkInfo, table: &str, col: &ColumnInfo, ref_table: &str, ref_col: &ColumnInfo) -> Vec<Lint> {
+    let mut res = Vec::new();
+    let mut add = |kind: LintKind, child: &str, parent: &str| res.push(Lint {
+        constraint: fk.name.clone(),
+        kind,
+        detail: format!("{table}.{} {child}, {ref_table}.{} {parent}", col.name, ref_col.name),
+    });
+    if !col.data_type.eq_ignore_ascii_case(&ref_col.data_type) {
+        add(LintKind::TypeMismatch, &col.column_type, &ref_col.column_type);
+    } else {
+        if col.is_unsigned() != ref_col.is_unsigned() {
+            add(LintKind::SignednessMismatch, &col.column_type, &ref_col.column_type);
+        }
+        if col.length() != ref_col.length() {
+            add(LintKind::LengthMismatch, &col.column_type, &ref_col.column_type);
+        }
+    }
+    match (&col.charset, &ref_col.charset) {
+        (Some(charset), Some(ref_charset)) if charset != ref_charset => add(LintKind::CharsetMismatch, charset, ref_charset),
+        _ => match (&col.collation, &ref_col.collation) {
+            (Some(collation), Some(ref_collation)) if collation != ref_collation => add(LintKind::CollationMismatch, collation, ref_collation),
+            _ => {}
+        },
+    }
+    res
+}
+
+/// Structural problems of a constraint, `tables` must contain the tables of both sides
+pub fn lint_fk(fk: &FkInfo, tables: &[TableInfo]) -> Vec<Lint> {
+    let mut res = Vec::new();
+    let table = find_table(tables, &fk.schema, &fk.table);
+    let ref_table = find_table(tables, &fk.ref_schema, &fk.ref_table);
+    for (c, rc) in fk.columns.iter().zip(fk.ref_columns.iter()) {
+        let col = table.and_then(|t| t.column(c));
+        let ref_col = ref_table.and_then(|t| t.column(rc));
+        match (col, ref_col) {
+            (Some(col), Some(ref_col)) => res.extend(column_lints(fk, &fk.table, col, &fk.ref_table, ref_col)),
+            (None, _) => res.push(Lint { constraint: fk.name.clone(), kind: LintKind::UnknownColumn, detail: format!("{}.{c}", fk.table) }),
+            (_, None) => res.push(Lint { constraint: fk.name.clone(), kind: LintKind::UnknownColumn, detail: format!("{}.{rc}", fk.ref_table) }),
+        }
+    }
+    if let Some(ref_table) = ref_table {
+        if !ref_table.has_unique_index(&fk.ref_columns) {
+            res.push(Lint { constraint: fk.name.clone(), kind: LintKind::NotUnique, detail: format!("{} ({})", fk.ref_table, fk.ref_columns.join(", ")) });
+        }
+    }
+    res
+}
+
+/// Structural problems of all the constraints, but the references embedded in column values
+pub fn lint(fk_idx: &FkIndex, tables: &[TableInfo]) -> Vec<Lint> {
+    fk_idx.fks.iter().filter(|fk| !fk.is_embedded()).flat_map(|fk| lint_fk(fk, tables)).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::lint;
+    use crate::ddl;
+    use crate::fk::FkIndex;
+
+    #[test]
+    fn test_lint() {
+        let ddl = ddl::parse(r"
+            CREATE TABLE parent (id bigint PRIMARY KEY, code varchar(20) CHARACTER SET utf8mb4, name varchar(10) COLLATE utf8mb4_bin, ref int) DEFAULT CHARSET=utf8mb4;
+            CREATE TABLE child (
+                parent_id int unsigned REFERENCES parent (id),
+                parent_code varchar(10) CHARACTER SET utf8mb3 REFERENCES parent (code),
+                parent_name varchar(10) REFERENCES parent (name),
+                parent_ref int REFERENCES parent (ref),
+                other_id int REFERENCES other (id)
+            ) DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_general_ci;", "sch");
+        let tables = ddl.tables.clone();
+        let res: Vec<String> = lint(&FkIndex::from(ddl.fks), &tables).iter().map(|l| l.to_string()).collect();
+        assert_eq!(res, vec![
+            "child_ibfk_1: type mismatch, child.parent_id int unsigned, parent.id bigint",
+            "child_ibfk_2: length mismatch, child.parent_code varchar(10), parent.code varchar(20)",
+            "child_ibfk_2: charset mismatch, child.parent_code utf8mb3, parent.code utf8mb4",
+            "child_ibfk_2: referenced columns not covered by a unique index, parent (code)",
+            "child_ibfk_3: collation mismatch, child.parent_name utf8mb4_general_ci, parent.name utf8mb4_bin",
+            "child_ibfk_3: referenced columns not covered by a unique index, parent (name)",
+            "child_ibfk_4: referenced columns not covered by a unique index, parent (ref)",
+            "child_ibfk_5: unknown column, other.id",
+        ]);
+    }
+}
@@ -4,5 +4,6 @@ pub mod utils;
 use utils::exit_on_err;
 
 fn main() {
-    mysql_fk_fixer::run(exit_on_err!(parse_args(), "Could not parse CLI arguments"));
+    let status = mysql_fk_fixer::run(exit_on_err!(parse_args(), "Could not parse CLI arguments"));
+    std::process::exit(status);
 }
@@ -0,0 +1,213 @@
+//! Promotion of the virtual FKs of the config file to real constraints, once their data is clean
+
+use std::fmt::Display;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::advisor;
+use crate::fk::FkInfo;
+use crate::lint;
+use crate::table::TableInfo;
+
+/// Naming and layout of the migration file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MigrationFormat {
+    /// V<timestamp>__promote_virtual_fks.sql
+    #[default]
+    Flyway,
+    /// <timestamp>_promote_virtual_fks.sql, a formatted SQL changelog with one changeset per constraint
+    Liquibase,
+}
+
+impl FromStr for MigrationFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "flyway" => Ok(MigrationFormat::Flyway),
+            "liquibase" => Ok(MigrationFormat::Liquibase),
+            _ => Err(format!("Unknown migration format {s}, expected flyway or liquibase")),
+        }
+    }
+}
+
+/// The verification of a virtual FK before its promotion
+#[derive(Debug)]
+pub struct Promotion {
+    pub fk: Rc<FkInfo>,
+    /// Reasons preventing the promotion: orphan rows, incompatible columns, missing index on the referenced table
+    pub problems: Vec<String>,
+    /// Not preventing the promotion, eg: MySQL creating an index on the FK columns
+    pub warnings: Vec<String>,
+}
+
+impl Promotion {
+    pub fn is_ready(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for Promotion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ready() {
+            write!(f, "{} can be promoted", self.fk.name)?;
+        } else {
+            write!(f, "{} can not be promoted", self.fk.name)?;
+        }
+        for problem in self.problems.iter() {
+            write!(f, "\n  {problem}")?;
+        }
+        for warning in self.warnings.iter() {
+            write!(f, "\n  WARNING: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Verify a virtual FK having `orphans` invalid references. `tables` must contain the tables of both sides.
+pub fn verify(fk: &Rc<FkInfo>, tables: &[TableInfo], orphans: usize) -> Promotion {
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+    if orphans > 0 {
+        problems.push(format!("{orphans} invalid foreign references"));
+    }
+    for table in tables.iter().filter(|t| (t.schema == fk.schema && t.name == fk.table) || (t.schema == fk.ref_schema && t.name == fk.ref_table)) {
+        if let Some(engine) = table.engine.as_deref().filter(|e| !e.eq_ignore_ascii_case("InnoDB")) {
+            problems.push(format!("table {} uses engine {engine}, only InnoDB enforces Foreign Keys", table.name));
+        } else if table.partitioned {
+            problems.push(format!("table {} is partitioned, InnoDB does not support Foreign Keys on partitioned tables", table.name));
+        }
+    }
+    problems.extend(lint::lint_fk(fk, tables).iter().map(|l| l.to_string()));
+    if let Some(advice) = advisor::advise_fk(fk, tables) {
+        if !advice.parent_indexed {
+            problems.push(format!("no index on referenced table {} {}", fk.ref_table, FkInfo::column_desc(&fk.ref_columns)));
+        }
+        if !advice.child_indexed {
+            warnings.push(format!("MySQL will create an index on table {} {}", fk.table, FkInfo::column_desc(&fk.columns)));
+        }
+    }
+    Promotion { fk: fk.clone(), problems, warnings }
+}
+
+fn quoted_columns(columns: &[String]) -> String {
+    columns.iter().map(|c| format!("`{c}`")).collect::<Vec<String>>().join(", ")
+}
+
+/// eg: ALTER TABLE `shop`.`line` ADD CONSTRAINT `vfk_line_order_id` FOREIGN KEY (`order_id`) REFERENCES `shop`.`order` (`id`) ON DELETE CASCADE;
+pub fn add_constraint(fk: &FkInfo) -> String {
+    format!("ALTER TABLE `{}`.`{}` ADD CONSTRAINT `{}` FOREIGN KEY ({}) REFERENCES `{}`.`{}` ({}) ON DELETE {};",
+        fk.schema, fk.table, fk.name, quoted_columns(&fk.columns),
+        fk.ref_schema, fk.ref_table, quoted_columns(&fk.ref_columns), fk.on_delete)
+}
+
+fn drop_constraint(fk: &FkInfo) -> String {
+    format!("ALTER TABLE `{}`.`{}` DROP FOREIGN KEY `{}`;", fk.schema, fk.table, fk.name)
+}
+
+/// File name and content of the migration creating the constraints, `unix_time` in seconds gives its version
+pub fn migration(format: MigrationFormat, fks: &[Rc<FkInfo>], unix_time: u64) -> (String, String) {
+    let version = timestamp(unix_time);
+    match format {
+        MigrationFormat::Flyway => {
+            let mut content = String::from("-- Promotion of virtual Foreign Keys\n");
+            for fk in fks.iter() {
+                content += &add_constraint(fk);
+                content.push('\n');
+            }
+            (format!("V{version}__promote_virtual_fks.sql"), content)
+        }
+        MigrationFormat::Liquibase => {
+            let mut content = String::from("--liquibase formatted sql\n");
+            for fk in fks.iter() {
+                content += &format!("\n--changeset mysql-fk-fixer:{version}-{}\n{}\n--rollback {}\n", fk.name, add_constraint(fk), drop_constraint(fk));
+            }
+            (format!("{version}_promote_virtual_fks.sql"), content)
+        }
+    }
+}
+
+/// UTC date of a unix time as YYYYMMDDhhmmss
+fn timestamp(unix_time: u64) -> String {
+    let days = (unix_time / 86400) as i64;
+    let secs = unix_time % 86400;
+    // Civil date from days since 1970-01-01, proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}{month:02}{day:02}{:02}{:02}{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::{add_constraint, migration, timestamp, verify, MigrationFormat};
+    use crate::ddl;
+    use crate::fk::{FkAction, FkInfo};
+
+    #[test]
+    fn test_verify() {
+        let mut ddl = ddl::parse(r"
+            CREATE TABLE parent (id int PRIMARY KEY, code varchar(10));
+            CREATE TABLE child (id int PRIMARY KEY, parent_id int, parent_code varchar(10), KEY idx_parent (parent_id));", "sch");
+        let fk = |name: &str, column: &str, ref_column: &str| Rc::new(FkInfo {
+            name: String::from(name),
+            schema: String::from("sch"),
+            table: String::from("child"),
+            columns: vec![String::from(column)],
+            ref_schema: String::from("sch"),
+            ref_table: String::from("parent"),
+            ref_columns: vec![String::from(ref_column)],
+            on_delete: FkAction::SetNull,
+            is_virtual: true,
+            ..Default::default()
+        });
+
+        let res = verify(&fk("vfk_parent", "parent_id", "id"), &ddl.tables, 0);
+        assert!(res.is_ready());
+        assert!(res.warnings.is_empty());
+        assert_eq!(add_constraint(&res.fk), "ALTER TABLE `sch`.`child` ADD CONSTRAINT `vfk_parent` FOREIGN KEY (`parent_id`) REFERENCES `sch`.`parent` (`id`) ON DELETE SET NULL;");
+
+        let res = verify(&fk("vfk_parent", "parent_id", "id"), &ddl.tables, 3);
+        assert_eq!(res.problems, vec!["3 invalid foreign references"]);
+
+        let res = verify(&fk("vfk_code", "parent_code", "code"), &ddl.tables, 0);
+        assert!(!res.is_ready());
+        assert_eq!(res.problems.len(), 2);
+        assert_eq!(res.warnings, vec!["MySQL will create an index on table child column parent_code"]);
+
+        ddl.tables[0].engine = Some(String::from("MyISAM"));
+        let res = verify(&fk("vfk_parent", "parent_id", "id"), &ddl.tables, 0);
+        assert_eq!(res.problems, vec!["table parent uses engine MyISAM, only InnoDB enforces Foreign Keys"]);
+        ddl.tables[0].engine = None;
+        ddl.tables[1].partitioned = true;
+        let res = verify(&fk("vfk_parent", "parent_id", "id"), &ddl.tables, 0);
+        assert_eq!(res.problems, vec!["table child is partitioned, InnoDB does not support Foreign Keys on partitioned tables"]);
+    }
+
+    #[test]
+    fn test_migration() {
+        assert_eq!(timestamp(0), "19700101000000");
+        assert_eq!(timestamp(1709210096), "20240229123456");
+
+        let fks: Vec<Rc<FkInfo>> = ddl::parse("CREATE TABLE a (b_id int REFERENCES b (id) ON DELETE CASCADE);", "sch").fks.into_iter().map(Rc::new).collect();
+        let (name, content) = migration(MigrationFormat::Flyway, &fks, 0);
+        assert_eq!(name, "V19700101000000__promote_virtual_fks.sql");
+        assert!(content.ends_with("REFERENCES `sch`.`b` (`id`) ON DELETE CASCADE;\n"));
+
+        let (name, content) = migration(MigrationFormat::Liquibase, &fks, 0);
+        assert_eq!(name, "19700101000000_promote_virtual_fks.sql");
+        assert!(content.starts_with("--liquibase formatted sql\n\n--changeset mysql-fk-fixer:19700101000000-a_ibfk_1\nALTER TABLE"));
+        assert!(content.contains("--rollback ALTER TABLE `sch`.`a` DROP FOREIGN KEY `a_ibfk_1`;"));
+        assert!("Flyway".parse::<MigrationFormat>().is_ok());
+        assert!("alembic".parse::<MigrationFormat>().is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Results of the checks, by constraint and kind of violation
+
+use std::fmt::Display;
+
+use serde::Deserialize;
+
+use crate::fk::FkInfo;
+
+/// Whether the rows of a violation fail the run (non-zero exit code), or are only warned about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// What is wrong with the rows of a violation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The referenced row does not exist
+    MissingParent,
+    /// Descendants of a row of a self-referencing table whose referenced row does not exist
+    OrphanedSubtree,
+    /// Rows of a self-referencing table referencing each other in a loop
+    Cycle,
+    /// The referenced row exists, but in another scope (eg: tenant)
+    CrossScope,
+    /// The referenced row exists, but is soft-deleted
+    SoftDeletedParent,
+    /// The referenced row exists, but its validity period does not contain the date of the row
+    NotValidAtTime,
+    /// Referenced rows with fewer referencing rows than required
+    TooFewChildren,
+    /// Referenced rows with more referencing rows than allowed
+    TooManyChildren,
+    /// The type column of a polymorphic relationship has a value without referenced table
+    UnknownType,
+    /// Rows returned by the query of an assertion of the config file
+    FailedAssertion,
+    /// Rows of a parent table referenced by no row of its child tables (--unreferenced)
+    Unreferenced,
+}
+
+impl Display for ViolationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ViolationKind::MissingParent => write!(f, "invalid foreign references"),
+            ViolationKind::OrphanedSubtree => write!(f, "descendants of invalid foreign references"),
+            ViolationKind::Cycle => write!(f, "rows in reference cycles"),
+            ViolationKind::CrossScope => write!(f, "references to another scope"),
+            ViolationKind::SoftDeletedParent => write!(f, "references to a soft-deleted parent"),
+            ViolationKind::NotValidAtTime => write!(f, "references to a parent existing but not valid at that time"),
+            ViolationKind::TooFewChildren => write!(f, "parent rows with too few children"),
+            ViolationKind::TooManyChildren => write!(f, "parent rows with too many children"),
+            ViolationKind::UnknownType => write!(f, "references with an unknown type"),
+            ViolationKind::FailedAssertion => write!(f, "rows failing the assertion"),
+            ViolationKind::Unreferenced => write!(f, "rows referenced by no child"),
+        }
+    }
+}
+
+/// Rows of a table violating a constraint
+#[derive(Debug)]
+pub struct Violation {
+    pub constraint: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub kind: ViolationKind,
+    pub count: usize,
+    pub severity: Severity,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.kind == ViolationKind::FailedAssertion {
+            return write!(f, "{} {} {}", self.count, self.kind, self.constraint);
+        }
+        write!(f, "{} {} found in table {} {}", self.count, self.kind, self.table, FkInfo::column_desc(&self.columns))
+    }
+}
+
+/// All the violations found during a run
+#[derive(Debug, Default)]
+pub struct Report {
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    /// Record `count` rows of the table of `fk` violating it, nothing is recorded if count is 0
+    pub fn add(&mut self, fk: &FkInfo, kind: ViolationKind, count: usize) -> Option<&Violation> {
+        self.add_rows(&fk.name, &fk.table, &fk.columns, kind, count)
+    }
+
+    /// Record `count` rows of `table` violating a constraint or relationship, nothing is recorded if count is 0
+    pub fn add_rows(&mut self, constraint: &str, table: &str, columns: &[String], kind: ViolationKind, count: usize) -> Option<&Violation> {
+        self.add_with_severity(constraint, table, columns, kind, count, Severity::Error)
+    }
+
+    /// Same as add_rows(), for a violation which may only be a warning
+    pub fn add_with_severity(&mut self, constraint: &str, table: &str, columns: &[String], kind: ViolationKind, count: usize, severity: Severity) -> Option<&Violation> {
+        if count == 0 {
+            return None;
+        }
+        self.violations.push(Violation {
+            constraint: constraint.to_string(),
+            table: table.to_string(),
+            columns: columns.to_vec(),
+            kind,
+            count,
+            severity,
+        });
+        self.violations.last()
+    }
+
+    /// Number of rows violating the given constraint, all kinds included
+    pub fn count(&self, constraint: &str) -> usize {
+        self.violations.iter()
+            .filter(|v| v.constraint == constraint)
+            .map(|v| v.count)
+            .sum()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// A violation fails the run, warnings excluded
+    pub fn has_errors(&self) -> bool {
+        self.violations.iter().any(|v| v.severity == Severity::Error)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Report, Severity, ViolationKind};
+    use crate::fk::FkInfo;
+    use mysql::Value;
+
+    #[test]
+    fn test_report() {
+        let fk = FkInfo::new((Value::from("fk1"), Value::from("sch"), Value::from("tb1"), Value::from("col"), Value::from("rt1"), Value::from("id")));
+        let mut report = Report::default();
+        assert!(report.add(&fk, ViolationKind::MissingParent, 0).is_none());
+        assert!(report.is_clean());
+        assert!(!report.has_errors());
+
+        assert!(report.add(&fk, ViolationKind::MissingParent, 3).is_some());
+        assert!(!report.is_clean());
+        assert!(report.has_errors());
+        assert_eq!(report.count("fk1"), 3);
+        assert_eq!(report.count("fk2"), 0);
+        assert_eq!(report.violations[0].to_string(), "3 invalid foreign references found in table tb1 column col");
+
+        let res = report.add_rows("poly", "comment", &[String::from("commentable_type")], ViolationKind::UnknownType, 2);
+        assert_eq!(res.expect("violation").to_string(), "2 references with an unknown type found in table comment column commentable_type");
+        assert_eq!(report.count("poly"), 2);
+
+        let res = report.add_rows("negative_total", "", &[], ViolationKind::FailedAssertion, 4);
+        assert_eq!(res.expect("violation").to_string(), "4 rows failing the assertion negative_total");
+
+        let res = report.add_with_severity("customer_unreferenced", "customer", &[String::from("id")], ViolationKind::Unreferenced, 12, Severity::Warning);
+        assert_eq!(res.expect("violation").to_string(), "12 rows referenced by no child found in table customer column id");
+    }
+
+    #[test]
+    fn test_severity() {
+        let mut report = Report::default();
+        assert!(report.add_with_severity("duplicate_subscriptions", "", &[], ViolationKind::FailedAssertion, 2, Severity::Warning).is_some());
+        assert!(!report.is_clean());
+        assert!(!report.has_errors());
+        assert!(report.add_with_severity("negative_total", "", &[], ViolationKind::FailedAssertion, 1, Severity::Error).is_some());
+        assert!(report.has_errors());
+    }
+}
@@ -0,0 +1,80 @@
+//! Flavor and version of the server, for the features which depend on them (recursive CTEs, JSON_TABLE).
+//! MariaDB 10.x reports a version above 8.0 in its handshake, so the version alone does not tell MySQL 8 from MariaDB.
+
+use mysql::prelude::Queryable;
+use mysql::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    MySql,
+    MariaDb,
+}
+
+/// Result of SELECT VERSION(), eg: 8.0.32 or 10.6.12-MariaDB-log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerVersion {
+    pub flavor: Flavor,
+    pub version: (u16, u16, u16),
+}
+
+impl ServerVersion {
+    pub fn query<C>(conn: &mut C) -> Result<Self>
+        where C: Queryable
+    {
+        let version: Option<String> = conn.query_first("SELECT VERSION()")?;
+        Ok(Self::parse(version.as_deref().unwrap_or_default()))
+    }
+
+    pub fn parse(version: &str) -> Self {
+        let flavor = if version.to_lowercase().contains("mariadb") { Flavor::MariaDb } else { Flavor::MySql };
+        // Prefix added by MariaDB for the replication with old MySQL servers
+        let version = version.strip_prefix("5.5.5-").unwrap_or(version);
+        let mut numbers = version.split(['.', '-']).map(|n| n.parse::<u16>().unwrap_or_default());
+        let mut next = || numbers.next().unwrap_or_default();
+        ServerVersion { flavor, version: (next(), next(), next()) }
+    }
+
+    /// WITH RECURSIVE: MySQL 8.0.1, MariaDB 10.2.2
+    pub fn has_recursive_cte(&self) -> bool {
+        match self.flavor {
+            Flavor::MySql => self.version >= (8, 0, 1),
+            Flavor::MariaDb => self.version >= (10, 2, 2),
+        }
+    }
+
+    /// JSON_TABLE: MySQL 8.0.4, MariaDB 10.6.0
+    pub fn has_json_table(&self) -> bool {
+        match self.flavor {
+            Flavor::MySql => self.version >= (8, 0, 4),
+            Flavor::MariaDb => self.version >= (10, 6, 0),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Flavor, ServerVersion};
+
+    #[test]
+    fn test_parse() {
+        let mysql = ServerVersion::parse("8.0.32");
+        assert_eq!(mysql, ServerVersion { flavor: Flavor::MySql, version: (8, 0, 32) });
+        assert!(mysql.has_recursive_cte());
+        assert!(!ServerVersion::parse("5.7.44-log").has_recursive_cte());
+
+        let mariadb = ServerVersion::parse("10.1.48-MariaDB-0+deb9u2");
+        assert_eq!(mariadb, ServerVersion { flavor: Flavor::MariaDb, version: (10, 1, 48) });
+        assert!(!mariadb.has_recursive_cte());
+        assert_eq!(ServerVersion::parse("5.5.5-10.6.12-MariaDB-log").version, (10, 6, 12));
+        assert!(ServerVersion::parse("10.6.12-MariaDB-log").has_recursive_cte());
+    }
+
+    #[test]
+    fn test_json_table() {
+        assert!(ServerVersion::parse("8.0.32").has_json_table());
+        assert!(!ServerVersion::parse("8.0.3-rc").has_json_table());
+        assert!(!ServerVersion::parse("10.5.19-MariaDB").has_json_table());
+        assert!(ServerVersion::parse("10.6.0-MariaDB").has_json_table());
+    }
+}
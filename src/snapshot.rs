@@ -0,0 +1,96 @@
+//! Schema snapshot (JSON format), the FK constraints saved from a run to be reused without discovery
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fk::{FkIndex, FkInfo, FkSource};
+use crate::table::TableInfo;
+
+/// Content of a snapshot file (options --save-schema and --load-schema)
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// eg: 8.0.32, None if the schema was not read from a server
+    #[serde(default)]
+    pub server_version: Option<String>,
+    pub fks: Vec<FkInfo>,
+    /// The tables having or referenced by an FK
+    #[serde(default)]
+    pub tables: Vec<TableInfo>,
+}
+
+impl Snapshot {
+    /// Snapshot of the FKs of an index, except the virtual FKs of the config file which stay in the config
+    pub fn new(fk_idx: &FkIndex, tables: Vec<TableInfo>, server_version: Option<String>) -> Self {
+        let fks: Vec<FkInfo> = fk_idx.fks.iter()
+            .filter(|fk| fk.source != FkSource::Config)
+            .map(|fk| (**fk).clone())
+            .collect();
+        let tables = tables.into_iter()
+            .filter(|t| fks.iter().any(|fk| (fk.schema == t.schema && fk.table == t.name) || (fk.ref_schema == t.schema && fk.ref_table == t.name)))
+            .collect();
+        Snapshot { server_version, fks, tables }
+    }
+
+    /// Read and parse a snapshot file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> io::Result<Self> {
+        serde_json::from_str(content).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("snapshot serialization")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Snapshot;
+    use crate::ddl;
+    use crate::fk::{FkAction, FkIndex, FkInfo, FkSource};
+    use mysql::Value;
+
+    #[test]
+    fn test_round_trip() {
+        let fk = FkInfo {
+            on_delete: FkAction::SetNull,
+            ..FkInfo::new((Value::from("fk1"), Value::from("sch"), Value::from("tb1"), Value::from("col"), Value::from("rt1"), Value::from("id")))
+        };
+        let json = Snapshot { fks: vec![fk], ..Default::default() }.to_json();
+        assert!(json.contains("\"on_delete\": \"SET NULL\""));
+
+        let snapshot = Snapshot::parse(&json).expect("snapshot should parse");
+        assert_eq!(snapshot.fks.len(), 1);
+        assert_eq!(snapshot.fks[0].name, "fk1");
+        assert_eq!(snapshot.fks[0].ref_columns, vec!["id"]);
+        assert_eq!(snapshot.fks[0].on_delete, FkAction::SetNull);
+        assert!(Snapshot::parse("{\"fks\": [{\"name\": \"x\"}]}").is_err());
+    }
+
+    #[test]
+    fn test_new() {
+        let ddl = ddl::parse("CREATE TABLE a (id int PRIMARY KEY); CREATE TABLE b (a_id int REFERENCES a (id)); CREATE TABLE c (id int);", "sch");
+        let mut fks = ddl.fks.clone();
+        fks.push(FkInfo { name: String::from("vfk"), source: FkSource::Config, ..fks[0].clone() });
+        let snapshot = Snapshot::new(&FkIndex::from(fks), ddl.tables.clone(), Some(String::from("8.0.32")));
+        assert_eq!(snapshot.fks.len(), 1);
+        assert_eq!(snapshot.fks[0].name, "b_ibfk_1");
+        assert_eq!(snapshot.tables.iter().map(|t| t.name.as_str()).collect::<Vec<&str>>(), vec!["a", "b"]);
+        assert_eq!(snapshot.tables[0].primary_key, vec!["id"]);
+
+        let snapshot = Snapshot::parse(&snapshot.to_json()).expect("snapshot should parse");
+        assert_eq!(snapshot.server_version.as_deref(), Some("8.0.32"));
+        assert_eq!(snapshot.tables[1].columns[0].data_type, "int");
+    }
+}
@@ -0,0 +1,129 @@
+//! Sources of FK constraint metadata, merged into one FkIndex
+
+use std::io::{self, ErrorKind};
+
+use mysql::prelude::Queryable;
+use mysql::Result;
+
+use crate::config::Config;
+use crate::ddl::DdlSchema;
+use crate::fk::{FkIndex, FkInfo, FkSource};
+use crate::snapshot::Snapshot;
+
+/// Provides FK constraints
+pub trait MetadataSource {
+    /// Recorded in the FKs of the source
+    fn kind(&self) -> FkSource;
+
+    /// The FK constraints, only those of `schema` if set
+    fn fk_constraints(&mut self, schema: Option<&String>) -> Result<Vec<FkInfo>>;
+}
+
+/// The FK constraints declared in the database
+pub struct InformationSchema<'c, C: Queryable> {
+    pub conn: &'c mut C,
+}
+
+impl<C: Queryable> MetadataSource for InformationSchema<'_, C> {
+    fn kind(&self) -> FkSource {
+        FkSource::InformationSchema
+    }
+
+    fn fk_constraints(&mut self, schema: Option<&String>) -> Result<Vec<FkInfo>> {
+        FkInfo::query_fk_constraints(self.conn, schema)
+    }
+}
+
+impl MetadataSource for DdlSchema {
+    fn kind(&self) -> FkSource {
+        FkSource::Ddl
+    }
+
+    fn fk_constraints(&mut self, schema: Option<&String>) -> Result<Vec<FkInfo>> {
+        Ok(self.fks.iter().filter(|fk| schema.is_none_or(|s| *s == fk.schema)).cloned().collect())
+    }
+}
+
+impl MetadataSource for Snapshot {
+    fn kind(&self) -> FkSource {
+        FkSource::Snapshot
+    }
+
+    fn fk_constraints(&mut self, schema: Option<&String>) -> Result<Vec<FkInfo>> {
+        Ok(self.fks.iter().filter(|fk| schema.is_none_or(|s| *s == fk.schema)).cloned().collect())
+    }
+}
+
+impl MetadataSource for Config {
+    fn kind(&self) -> FkSource {
+        FkSource::Config
+    }
+
+    fn fk_constraints(&mut self, schema: Option<&String>) -> Result<Vec<FkInfo>> {
+        Ok(self.virtual_fk_infos(schema)?)
+    }
+}
+
+/// Merge the FK constraints of the sources, in order, into one index.
+/// Constraint names must be unique across the sources.
+pub fn merge(sources: &mut [&mut dyn MetadataSource], schema: Option<&String>) -> Result<FkIndex> {
+    let mut fks: Vec<FkInfo> = Vec::new();
+    for source in sources.iter_mut() {
+        let kind = source.kind();
+        for fk in source.fk_constraints(schema)? {
+            if let Some(other) = fks.iter().find(|f| f.name == fk.name) {
+                let msg = format!("Foreign key {} of the {kind} has the same name as another constraint of the {}", fk.name, other.source);
+                return Err(io::Error::new(ErrorKind::InvalidData, msg).into());
+            }
+            fks.push(FkInfo { source: kind, ..fk });
+        }
+    }
+    Ok(FkIndex::from(fks))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{merge, MetadataSource};
+    use crate::config::Config;
+    use crate::ddl;
+    use crate::fk::FkSource;
+    use crate::snapshot::Snapshot;
+
+    const CONFIG: &str = r#"
+        [[virtual_fk]]
+        table = "baz"
+        columns = ["foo_id"]
+        ref_table = "foo"
+        ref_columns = ["id"]
+    "#;
+
+    #[test]
+    fn test_merge() {
+        let mut ddl = ddl::parse("CREATE TABLE a (id int PRIMARY KEY, b_id int REFERENCES b (id));", "sch");
+        let mut config = Config::parse(CONFIG).unwrap();
+        let schema = String::from("sch");
+        let fk_idx = merge(&mut [&mut ddl as &mut dyn MetadataSource, &mut config], Some(&schema)).expect("merge should succeed");
+        assert_eq!(fk_idx.fks.len(), 2);
+        assert_eq!(fk_idx.fks_by_name["a_ibfk_1"].source, FkSource::Ddl);
+        assert_eq!(fk_idx.fks_by_name["vfk_baz_foo_id"].source, FkSource::Config);
+
+        let other = String::from("other");
+        let fk_idx = merge(&mut [&mut ddl as &mut dyn MetadataSource], Some(&other)).expect("merge should succeed");
+        assert!(fk_idx.fks.is_empty());
+    }
+
+    #[test]
+    fn test_merge_duplicate_name() {
+        let mut ddl = ddl::parse("CREATE TABLE baz (foo_id int, CONSTRAINT vfk_baz_foo_id FOREIGN KEY (foo_id) REFERENCES foo (id));", "sch");
+        let mut snapshot = Snapshot { fks: ddl.fks.clone(), ..Default::default() };
+        let mut config = Config::parse(CONFIG).unwrap();
+        let schema = String::from("sch");
+        let res = merge(&mut [&mut snapshot as &mut dyn MetadataSource, &mut config], Some(&schema));
+        let msg = res.err().expect("duplicate name").to_string();
+        assert!(msg.contains("same name"));
+        assert!(msg.contains("config file") && msg.contains("schema snapshot"));
+
+        assert!(merge(&mut [&mut ddl as &mut dyn MetadataSource, &mut snapshot], None).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Table and column definitions
+
+use mysql::*;
+use mysql::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::schema_filter;
+
+/// Definition of a column
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// eg: bigint
+    pub data_type: String,
+    /// Full type, eg: bigint(20) unsigned
+    pub column_type: String,
+    /// None for non-string columns
+    #[serde(default)]
+    pub charset: Option<String>,
+    #[serde(default)]
+    pub collation: Option<String>,
+}
+
+impl ColumnInfo {
+    pub fn is_unsigned(&self) -> bool {
+        self.column_type.to_lowercase().contains("unsigned")
+    }
+
+    /// Length of string, binary and fixed-point types, eg: 255 for varchar(255), 10,2 for decimal(10,2)
+    pub fn length(&self) -> Option<&str> {
+        if !LENGTH_TYPES.contains(&self.data_type.as_str()) {
+            return None;
+        }
+        let start = self.column_type.find('(')?;
+        let end = self.column_type[start..].find(')')?;
+        Some(&self.column_type[start + 1..start + end])
+    }
+}
+
+/// Types whose length matters, the display width of integer types does not
+const LENGTH_TYPES: [&str; 7] = ["char", "varchar", "binary", "varbinary", "decimal", "numeric", "bit"];
+
+/// Definition of an index
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexInfo {
+    /// PRIMARY for the primary key
+    pub name: String,
+    pub unique: bool,
+    /// In index order
+    pub columns: Vec<String>,
+}
+
+/// schema, table, engine, create options, rows, column, data type, column type, charset, collation
+type ColumnRow = (String, String, Option<String>, Option<String>, Option<u64>, String, String, String, Option<String>, Option<String>);
+
+/// CREATE_OPTIONS of information_schema.TABLES contains "partitioned" for partitioned tables
+fn is_partitioned(create_options: Option<String>) -> bool {
+    create_options.is_some_and(|o| o.to_lowercase().contains("partitioned"))
+}
+
+/// Definition of a table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableInfo {
+    pub schema: String,
+    pub name: String,
+    pub engine: Option<String>,
+    #[serde(default)]
+    pub partitioned: bool,
+    /// In definition order
+    pub columns: Vec<ColumnInfo>,
+    pub primary_key: Vec<String>,
+    /// All the indexes, primary key included. Functional indexes are ignored.
+    #[serde(default)]
+    pub indexes: Vec<IndexInfo>,
+    /// Estimated number of rows (TABLE_ROWS), None without a server
+    #[serde(default)]
+    pub rows: Option<u64>,
+}
+
+impl TableInfo {
+    /// Position of a column, column names are case insensitive
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnInfo> {
+        self.column_index(name).map(|idx| &self.columns[idx])
+    }
+
+    /// A unique index on `columns` or on a part of them exists, so that they identify at most one row
+    pub fn has_unique_index(&self, columns: &[String]) -> bool {
+        self.indexes.iter().any(|idx| idx.unique && idx.columns.iter().all(|c| columns.iter().any(|col| col.eq_ignore_ascii_case(c))))
+    }
+
+    /// An index can be used to look up `columns`: its leftmost columns are `columns`, in any order
+    pub fn has_leftmost_index(&self, columns: &[String]) -> bool {
+        self.indexes.iter().any(|idx| idx.columns.len() >= columns.len()
+            && idx.columns[..columns.len()].iter().all(|c| columns.iter().any(|col| col.eq_ignore_ascii_case(c))))
+    }
+
+    /// Only InnoDB tables which are not partitioned keep their FK constraints, the REFERENCES clauses
+    /// of the other tables are dropped. A table of unknown engine is assumed to be InnoDB.
+    pub fn enforces_fks(&self) -> bool {
+        self.engine.as_deref().is_none_or(|e| e.eq_ignore_ascii_case("InnoDB")) && !self.partitioned
+    }
+
+    /// Get the tables not enforcing FK constraints (other engines, partitioned tables), without their columns
+    pub fn query_unprotected<T>(conn: &mut T, schema: Option<&String>) -> Result<Vec<Self>>
+        where T: Queryable
+    {
+        let query = format!(
+            r"SELECT TABLE_SCHEMA, TABLE_NAME, ENGINE, CREATE_OPTIONS, TABLE_ROWS
+            FROM information_schema.TABLES
+            WHERE TABLE_TYPE='BASE TABLE' AND (ENGINE<>'InnoDB' OR CREATE_OPTIONS LIKE '%partitioned%') AND {}
+            ORDER BY TABLE_SCHEMA, TABLE_NAME",
+            schema_filter("TABLE_SCHEMA", schema));
+        conn.query_map(query, |(schema, name, engine, create_options, rows): (String, String, Option<String>, Option<String>, Option<u64>)| {
+            TableInfo { schema, name, engine, partitioned: is_partitioned(create_options), rows, ..Default::default() }
+        })
+    }
+
+    /// Names of the partitions of a table, or of its subpartitions if any, in order
+    pub fn query_partitions<T>(conn: &mut T, schema: &str, table: &str) -> Result<Vec<String>>
+        where T: Queryable
+    {
+        let query = format!(
+            r"SELECT COALESCE(SUBPARTITION_NAME, PARTITION_NAME)
+            FROM information_schema.PARTITIONS
+            WHERE TABLE_SCHEMA='{schema}' AND TABLE_NAME='{table}' AND PARTITION_NAME IS NOT NULL
+            ORDER BY PARTITION_ORDINAL_POSITION, SUBPARTITION_ORDINAL_POSITION");
+        conn.query(query)
+    }
+
+    /// Columns of the primary key of a table, in order, empty without primary key
+    pub fn query_primary_key<T>(conn: &mut T, schema: &str, table: &str) -> Result<Vec<String>>
+        where T: Queryable
+    {
+        let query = format!(
+            r"SELECT COLUMN_NAME
+            FROM information_schema.KEY_COLUMN_USAGE
+            WHERE TABLE_SCHEMA='{schema}' AND TABLE_NAME='{table}' AND CONSTRAINT_NAME='PRIMARY'
+            ORDER BY ORDINAL_POSITION");
+        conn.query(query)
+    }
+
+    /// Get the definitions of all the tables, from information_schema
+    pub fn query_tables<T>(conn: &mut T, schema: Option<&String>) -> Result<Vec<Self>>
+        where T: Queryable
+    {
+        let query = format!(
+            r"SELECT c.TABLE_SCHEMA, c.TABLE_NAME, t.ENGINE, t.CREATE_OPTIONS, t.TABLE_ROWS, c.COLUMN_NAME, c.DATA_TYPE, c.COLUMN_TYPE, c.CHARACTER_SET_NAME, c.COLLATION_NAME
+            FROM information_schema.COLUMNS c
+            JOIN information_schema.TABLES t ON t.TABLE_SCHEMA=c.TABLE_SCHEMA AND t.TABLE_NAME=c.TABLE_NAME
+            WHERE {}
+            ORDER BY c.TABLE_SCHEMA, c.TABLE_NAME, c.ORDINAL_POSITION",
+            schema_filter("c.TABLE_SCHEMA", schema));
+        let mut res: Vec<TableInfo> = Vec::new();
+        let rows: Vec<ColumnRow> = conn.query(query)?;
+        for (schema, name, engine, create_options, rows, column, data_type, column_type, charset, collation) in rows {
+            if !res.last().is_some_and(|t| t.schema == schema && t.name == name) {
+                res.push(TableInfo { schema, name, engine, partitioned: is_partitioned(create_options), rows, ..Default::default() });
+            }
+            let table = res.last_mut().expect("table of the column");
+            table.columns.push(ColumnInfo { name: column, data_type, column_type, charset, collation });
+        }
+
+        // COLUMN_NAME is NULL for the expressions of functional indexes
+        let query = format!(
+            r"SELECT TABLE_SCHEMA, TABLE_NAME, INDEX_NAME, NON_UNIQUE, COLUMN_NAME
+            FROM information_schema.STATISTICS
+            WHERE {}
+            ORDER BY TABLE_SCHEMA, TABLE_NAME, INDEX_NAME, SEQ_IN_INDEX",
+            schema_filter("TABLE_SCHEMA", schema));
+        let rows: Vec<(String, String, String, i64, Option<String>)> = conn.query(query)?;
+        let mut functional: Vec<(String, String, String)> = Vec::new();
+        for (schema, name, index, non_unique, column) in rows {
+            let Some(table) = res.iter_mut().find(|t| t.schema == schema && t.name == name) else {
+                continue;
+            };
+            let Some(column) = column else {
+                functional.push((schema, name, index));
+                continue;
+            };
+            if table.indexes.last().is_none_or(|idx| idx.name != index) {
+                table.indexes.push(IndexInfo { name: index, unique: non_unique == 0, columns: Vec::new() });
+            }
+            table.indexes.last_mut().expect("index of the column").columns.push(column);
+        }
+        for table in res.iter_mut() {
+            table.indexes.retain(|idx| !functional.iter().any(|(s, t, i)| *s == table.schema && *t == table.name && *i == idx.name));
+            if let Some(pk) = table.indexes.iter().find(|idx| idx.name == "PRIMARY") {
+                table.primary_key = pk.columns.clone();
+            }
+        }
+        Ok(res)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{is_partitioned, ColumnInfo, IndexInfo, TableInfo};
+
+    #[test]
+    fn test_column() {
+        let table = TableInfo {
+            name: String::from("foo"),
+            columns: vec![
+                ColumnInfo { name: String::from("id"), data_type: String::from("bigint"), column_type: String::from("bigint(20) unsigned"), ..Default::default() },
+                ColumnInfo { name: String::from("Val"), data_type: String::from("varchar"), column_type: String::from("varchar(255)"), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(table.column_index("val"), Some(1));
+        assert_eq!(table.column("ID").map(|c| c.data_type.as_str()), Some("bigint"));
+        assert!(table.column("other").is_none());
+
+        assert!(table.columns[0].is_unsigned());
+        assert!(table.columns[0].length().is_none());
+        assert_eq!(table.columns[1].length(), Some("255"));
+    }
+
+    #[test]
+    fn test_has_unique_index() {
+        let table = TableInfo {
+            indexes: vec![
+                IndexInfo { name: String::from("PRIMARY"), unique: true, columns: vec![String::from("id")] },
+                IndexInfo { name: String::from("idx"), unique: false, columns: vec![String::from("code")] },
+            ],
+            ..Default::default()
+        };
+        assert!(table.has_unique_index(&[String::from("id")]));
+        assert!(table.has_unique_index(&[String::from("ID"), String::from("other")]));
+        assert!(!table.has_unique_index(&[String::from("code")]));
+
+        assert!(table.has_leftmost_index(&[String::from("code")]));
+        assert!(!table.has_leftmost_index(&[String::from("code"), String::from("id")]));
+    }
+
+    #[test]
+    fn test_enforces_fks() {
+        let table = |engine: Option<&str>, partitioned: bool| TableInfo { engine: engine.map(String::from), partitioned, ..Default::default() };
+        assert!(table(Some("InnoDB"), false).enforces_fks());
+        assert!(table(None, false).enforces_fks());
+        assert!(!table(Some("MyISAM"), false).enforces_fks());
+        assert!(!table(Some("InnoDB"), true).enforces_fks());
+        assert!(is_partitioned(Some(String::from("row_format=DYNAMIC partitioned"))));
+        assert!(!is_partitioned(None));
+    }
+}
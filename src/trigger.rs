@@ -0,0 +1,209 @@
+//! Guard triggers enforcing virtual FKs which can not be real constraints, eg: on partitioned tables
+//! or across storage engines. The triggers SIGNAL an error when a change would break the reference.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use mysql::prelude::Queryable;
+use mysql::Result;
+
+use crate::fk::FkInfo;
+use crate::utils::schema_filter;
+
+/// Prefix of the names of the triggers managed by the tool
+pub const TRIGGER_PREFIX: &str = "fkg_";
+
+/// Maximum length of a trigger name
+const MAX_NAME_LEN: usize = 64;
+
+/// Maximum length of the message of a SIGNAL
+const MAX_MESSAGE_LEN: usize = 128;
+
+/// What to do with the guard triggers (option --guard-triggers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerCommand {
+    Install,
+    List,
+    Remove,
+}
+
+impl FromStr for TriggerCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "install" => Ok(TriggerCommand::Install),
+            "list" => Ok(TriggerCommand::List),
+            "remove" => Ok(TriggerCommand::Remove),
+            _ => Err(format!("Unknown guard trigger command {s}, expected install, list or remove")),
+        }
+    }
+}
+
+/// A trigger managed by the tool
+#[derive(Debug, Clone)]
+pub struct GuardTrigger {
+    pub schema: String,
+    pub name: String,
+    pub table: String,
+    /// eg: BEFORE INSERT
+    pub event: String,
+    /// BEGIN ... END
+    pub body: String,
+}
+
+impl GuardTrigger {
+    pub fn create_statement(&self) -> String {
+        format!("CREATE TRIGGER `{}`.`{}` {} ON `{}`.`{}` FOR EACH ROW {}", self.schema, self.name, self.event, self.schema, self.table, self.body)
+    }
+
+    pub fn drop_statement(&self) -> String {
+        format!("DROP TRIGGER IF EXISTS `{}`.`{}`", self.schema, self.name)
+    }
+
+    /// The managed triggers installed in the database
+    pub fn query_triggers<C>(conn: &mut C, schema: Option<&String>) -> Result<Vec<Self>>
+        where C: Queryable
+    {
+        let query = format!(
+            r"SELECT TRIGGER_SCHEMA, TRIGGER_NAME, EVENT_OBJECT_TABLE, ACTION_TIMING, EVENT_MANIPULATION, ACTION_STATEMENT
+            FROM information_schema.TRIGGERS
+            WHERE TRIGGER_NAME LIKE 'fkg\_%' AND {}
+            ORDER BY TRIGGER_SCHEMA, EVENT_OBJECT_TABLE, TRIGGER_NAME",
+            schema_filter("TRIGGER_SCHEMA", schema));
+        conn.query_map(query, |(schema, name, table, timing, event, body): (String, String, String, String, String, String)| {
+            GuardTrigger { schema, name, table, event: format!("{timing} {event}"), body }
+        })
+    }
+}
+
+impl Display for GuardTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Trigger {} in schema {} on table {} {}", self.name, self.schema, self.table, self.event)
+    }
+}
+
+/// eg: fkg_vfk_line_order_id_ins
+fn trigger_name(fk: &FkInfo, suffix: &str) -> String {
+    let mut name = format!("{TRIGGER_PREFIX}{}", fk.name);
+    while name.len() + suffix.len() + 1 > MAX_NAME_LEN {
+        name.pop();
+    }
+    format!("{name}_{suffix}")
+}
+
+fn signal(message: String) -> String {
+    let mut message = message.replace('\'', "''");
+    while message.len() > MAX_MESSAGE_LEN {
+        message.pop();
+    }
+    format!("SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = '{message}'")
+}
+
+/// `left`=`right` for each pair of columns, `right` being prefixed with NEW. or OLD.
+fn match_columns(left: &[String], row: &str, right: &[String]) -> String {
+    left.iter().zip(right.iter())
+        .map(|(l, r)| format!("`{l}` = {row}.`{r}`"))
+        .collect::<Vec<String>>()
+        .join(" AND ")
+}
+
+/// The triggers enforcing a virtual FK: BEFORE INSERT and BEFORE UPDATE on the child table,
+/// BEFORE DELETE and BEFORE UPDATE of the referenced columns on the parent table. Like a real FK, a reference with a NULL column is not checked.
+/// The triggers of a polymorphic branch only check the rows of its type.
+pub fn guard_triggers(fk: &FkInfo) -> Vec<GuardTrigger> {
+    let mut child_condition = fk.columns.iter()
+        .map(|c| format!("NEW.`{c}` IS NOT NULL"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    let mut parent_match = match_columns(&fk.columns, "OLD", &fk.ref_columns);
+    if let Some(d) = &fk.discriminator {
+        child_condition += &format!(" AND NEW.`{}` = {}", d.column, d.literal());
+        parent_match += &format!(" AND `{}` = {}", d.column, d.literal());
+    }
+    let child_check = format!(
+        "BEGIN IF {child_condition} AND NOT EXISTS (SELECT 1 FROM `{}`.`{}` WHERE {}) THEN {}; END IF; END",
+        fk.ref_schema, fk.ref_table, match_columns(&fk.ref_columns, "NEW", &fk.columns),
+        signal(format!("Cannot add or update a child row: virtual foreign key {} fails", fk.name)));
+    let parent_check = format!(
+        "BEGIN IF EXISTS (SELECT 1 FROM `{}`.`{}` WHERE {parent_match}) THEN {}; END IF; END",
+        fk.schema, fk.table,
+        signal(format!("Cannot delete a parent row: virtual foreign key {} fails", fk.name)));
+    let key_changed = fk.ref_columns.iter()
+        .map(|c| format!("OLD.`{c}` <=> NEW.`{c}`"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    let parent_update_check = format!(
+        "BEGIN IF NOT ({key_changed}) AND EXISTS (SELECT 1 FROM `{}`.`{}` WHERE {parent_match}) THEN {}; END IF; END",
+        fk.schema, fk.table,
+        signal(format!("Cannot update a parent row: virtual foreign key {} fails", fk.name)));
+    let child = |suffix: &str, event: &str| GuardTrigger {
+        schema: fk.schema.clone(),
+        name: trigger_name(fk, suffix),
+        table: fk.table.clone(),
+        event: String::from(event),
+        body: child_check.clone(),
+    };
+    let parent = |suffix: &str, event: &str, body: String| GuardTrigger {
+        schema: fk.ref_schema.clone(),
+        name: trigger_name(fk, suffix),
+        table: fk.ref_table.clone(),
+        event: String::from(event),
+        body,
+    };
+    vec![
+        child("ins", "BEFORE INSERT"),
+        child("upd", "BEFORE UPDATE"),
+        parent("del", "BEFORE DELETE", parent_check),
+        parent("pupd", "BEFORE UPDATE", parent_update_check),
+    ]
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{guard_triggers, TriggerCommand};
+    use crate::ddl;
+    use crate::fk::{Discriminator, FkInfo};
+
+    #[test]
+    fn test_guard_triggers() {
+        let fks = ddl::parse("CREATE TABLE line (order_id int, shop_id int, FOREIGN KEY (order_id, shop_id) REFERENCES `order` (id, shop_id));", "sch").fks;
+        let res = guard_triggers(&fks[0]);
+        assert_eq!(res.len(), 4);
+        assert_eq!(res[0].create_statement(), "CREATE TRIGGER `sch`.`fkg_line_ibfk_1_ins` BEFORE INSERT ON `sch`.`line` FOR EACH ROW \
+            BEGIN IF NEW.`order_id` IS NOT NULL AND NEW.`shop_id` IS NOT NULL \
+            AND NOT EXISTS (SELECT 1 FROM `sch`.`order` WHERE `id` = NEW.`order_id` AND `shop_id` = NEW.`shop_id`) \
+            THEN SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Cannot add or update a child row: virtual foreign key line_ibfk_1 fails'; END IF; END");
+        assert_eq!(res[1].event, "BEFORE UPDATE");
+        assert_eq!(res[2].create_statement(), "CREATE TRIGGER `sch`.`fkg_line_ibfk_1_del` BEFORE DELETE ON `sch`.`order` FOR EACH ROW \
+            BEGIN IF EXISTS (SELECT 1 FROM `sch`.`line` WHERE `order_id` = OLD.`id` AND `shop_id` = OLD.`shop_id`) \
+            THEN SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Cannot delete a parent row: virtual foreign key line_ibfk_1 fails'; END IF; END");
+        assert_eq!(res[2].drop_statement(), "DROP TRIGGER IF EXISTS `sch`.`fkg_line_ibfk_1_del`");
+        assert_eq!(res[3].create_statement(), "CREATE TRIGGER `sch`.`fkg_line_ibfk_1_pupd` BEFORE UPDATE ON `sch`.`order` FOR EACH ROW \
+            BEGIN IF NOT (OLD.`id` <=> NEW.`id` AND OLD.`shop_id` <=> NEW.`shop_id`) \
+            AND EXISTS (SELECT 1 FROM `sch`.`line` WHERE `order_id` = OLD.`id` AND `shop_id` = OLD.`shop_id`) \
+            THEN SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Cannot update a parent row: virtual foreign key line_ibfk_1 fails'; END IF; END");
+
+        let long = FkInfo { name: "x".repeat(100), ..fks[0].clone() };
+        let res = guard_triggers(&long);
+        assert_eq!(res[0].name.len(), 64);
+        assert!(res[0].name.ends_with("x_ins"));
+
+        let poly = FkInfo {
+            discriminator: Some(Discriminator { relationship: String::from("poly"), column: String::from("order_type"), value: String::from("Order") }),
+            ..fks[0].clone()
+        };
+        let res = guard_triggers(&poly);
+        assert!(res[0].body.starts_with("BEGIN IF NEW.`order_id` IS NOT NULL AND NEW.`shop_id` IS NOT NULL AND NEW.`order_type` = 'Order' AND NOT EXISTS"));
+        assert!(res[2].body.contains("WHERE `order_id` = OLD.`id` AND `shop_id` = OLD.`shop_id` AND `order_type` = 'Order')"));
+        assert!(res[3].body.contains("WHERE `order_id` = OLD.`id` AND `shop_id` = OLD.`shop_id` AND `order_type` = 'Order')"));
+    }
+
+    #[test]
+    fn test_trigger_command() {
+        assert_eq!("Install".parse::<TriggerCommand>(), Ok(TriggerCommand::Install));
+        assert_eq!("remove".parse::<TriggerCommand>(), Ok(TriggerCommand::Remove));
+        assert!("drop".parse::<TriggerCommand>().is_err());
+    }
+}
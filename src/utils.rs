@@ -1,18 +1,49 @@
-/// Run expression returning a Result<>, If Err() logs the error and return; else unwrap()
+use mysql::Value;
+
+/// Schemas never searched when no schema is given
+const SYSTEM_SCHEMAS: &str = "'mysql', 'information_schema', 'performance_schema', 'sys'";
+
+/// WHERE condition on the schema `column` of an information_schema table
+pub fn schema_filter(column: &str, schema: Option<&String>) -> String {
+    match schema {
+        Some(schema_name) => format!("{column}='{schema_name}'"),
+        None => format!("{column} NOT IN ({SYSTEM_SCHEMAS})"),
+    }
+}
+
+/// Values of a row as a map key. The text protocol (query) returns Bytes where the binary protocol (exec)
+/// returns Int or UInt, both give the same key.
+pub fn key_str(values: &[Value]) -> Vec<String> {
+    values.iter()
+        .map(|v| match v {
+            Value::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            Value::Int(i) => i.to_string(),
+            Value::UInt(u) => u.to_string(),
+            v => v.as_sql(false),
+        })
+        .collect()
+}
+
+/// Run expression returning a Result<>, If Err() logs the error and return (the optional value); else unwrap()
 /// Usage let result = exit_on_err!(try_do(), "Try do failed");
+/// or let result = exit_on_err!(try_do(), "Try do failed", EXIT_ERROR);
 macro_rules! exit_on_err {
     ( $x:expr, $y:expr ) => {
+        exit_on_err!($x, $y, ())
+    };
+    ( $x:expr, $y:expr, $ret:expr ) => {
         {
             let var = $x;
             if let Err(what) = var {
                 eprintln!("ERROR: {}", $y);
                 eprintln!("{what}");
-                return;
+                return $ret;
             }
             var.unwrap()
         }
     };
 }
+#[allow(unused_imports)]
 pub(crate) use exit_on_err;
 
 /// Run expression returning a Result<>, If Err() logs the error and return; else unwrap()